@@ -0,0 +1,252 @@
+//! ## Authenticated frames
+//!
+//! HMAC-SHA256 tags for frames crossing trust boundaries. Each frame carries the
+//! id of the key it was signed with and its tag, both as lowercase hex digits,
+//! between the payload and the mark:
+//!
+//! `payload|key id (2 digits)|tag (64 digits)|mark`
+//!
+//! The digits cannot run into a mark or tail that neither starts nor ends with one,
+//! which is checked before a frame is signed or verified.
+//!
+//! The tag covers the key id, the payload and the frame's sequence number within
+//! the session. The sequence number is never sent - both ends count frames - so a
+//! frame that is replayed, reordered or dropped no longer verifies.
+
+use crate::{hex, Byte};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const TAG_LEN: usize = 32;
+
+/// Number of bytes the key id and tag take up in a frame.
+pub const AUTH_LEN: usize = 2 * (1 + TAG_LEN);
+
+/// Why a frame failed authentication.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AuthFailure {
+    /// The frame is too short to hold a key id and tag, or they are not hex digits.
+    Malformed,
+    /// The frame was signed with a key the verifier does not have.
+    UnknownKey(u8),
+    /// The tag does not match - the frame was forged or modified, or is out of sequence.
+    BadTag,
+}
+
+impl fmt::Display for AuthFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthFailure::Malformed => write!(f, "malformed key id or tag"),
+            AuthFailure::UnknownKey(key_id) => write!(f, "unknown key id {}", key_id),
+            AuthFailure::BadTag => write!(f, "tag does not match"),
+        }
+    }
+}
+
+/// Signs the frames of one session, in order - see `ByteMarker::mark_signed`.
+#[derive(Clone)]
+pub struct Signer {
+    key_id: u8,
+    mac: HmacSha256,
+    sequence: u64,
+}
+
+impl Signer {
+    /// Starts a session signing with `key`, known to the reader as `key_id`.
+    pub fn new(key_id: u8, key: &[Byte]) -> Self {
+        Self {
+            key_id,
+            mac: keyed(key),
+            sequence: 0,
+        }
+    }
+
+    /// Signs the frames from here on with a new key. The session, and with it the
+    /// sequence, carries on.
+    pub fn rotate(&mut self, key_id: u8, key: &[Byte]) {
+        self.key_id = key_id;
+        self.mac = keyed(key);
+    }
+
+    /// Sequence number of the next frame.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Appends the key id and the tag of `payload` as the next frame to `out`.
+    pub fn sign(&mut self, payload: &[Byte], out: &mut Vec<Byte>) {
+        let tag = tag(self.mac.clone(), self.sequence, self.key_id, payload);
+        self.sequence += 1;
+        hex::push(&[self.key_id], out);
+        hex::push(&tag, out);
+    }
+}
+
+impl fmt::Debug for Signer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Signer")
+            .field("key_id", &self.key_id)
+            .field("sequence", &self.sequence)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Checks the frames of one session, in order, against the keys it holds.
+///
+/// A frame that fails does not use up a sequence number, so forged frames slipped
+/// into the stream do not stop the genuine ones that follow from verifying.
+#[derive(Clone, Default)]
+pub struct Verifier {
+    keys: BTreeMap<u8, HmacSha256>,
+    sequence: u64,
+}
+
+impl Verifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accepts frames signed with `key` under `key_id`, replacing any key it had
+    /// under that id.
+    pub fn with_key(mut self, key_id: u8, key: &[Byte]) -> Self {
+        self.add_key(key_id, key);
+        self
+    }
+
+    /// Like `with_key`, for rotating keys mid session.
+    pub fn add_key(&mut self, key_id: u8, key: &[Byte]) {
+        self.keys.insert(key_id, keyed(key));
+    }
+
+    /// Stops accepting frames signed under `key_id`.
+    pub fn remove_key(&mut self, key_id: u8) {
+        self.keys.remove(&key_id);
+    }
+
+    /// Sequence number of the next frame.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Checks `frame` as the next frame of the session, returning its payload with
+    /// the key id and tag split off.
+    pub fn verify<'f>(&mut self, frame: &'f [Byte]) -> Result<&'f [Byte], AuthFailure> {
+        let split = frame
+            .len()
+            .checked_sub(AUTH_LEN)
+            .ok_or(AuthFailure::Malformed)?;
+        let (payload, auth) = frame.split_at(split);
+        let mut decoded = [0; 1 + TAG_LEN];
+        hex::decode(auth, &mut decoded).ok_or(AuthFailure::Malformed)?;
+        let (key_id, tag) = (decoded[0], &decoded[1..]);
+        let mac = self
+            .keys
+            .get(&key_id)
+            .ok_or(AuthFailure::UnknownKey(key_id))?;
+        let mac = input(mac.clone(), self.sequence, key_id, payload);
+        //Compared in constant time
+        mac.verify_slice(tag).map_err(|_| AuthFailure::BadTag)?;
+        self.sequence += 1;
+        Ok(payload)
+    }
+}
+
+impl fmt::Debug for Verifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Verifier")
+            .field("key_ids", &self.keys.keys().collect::<Vec<_>>())
+            .field("sequence", &self.sequence)
+            .finish_non_exhaustive()
+    }
+}
+
+fn keyed(key: &[Byte]) -> HmacSha256 {
+    HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length")
+}
+
+fn input(mut mac: HmacSha256, sequence: u64, key_id: u8, payload: &[Byte]) -> HmacSha256 {
+    mac.update(&sequence.to_be_bytes());
+    mac.update(&[key_id]);
+    mac.update(payload);
+    mac
+}
+
+fn tag(mac: HmacSha256, sequence: u64, key_id: u8, payload: &[Byte]) -> [Byte; TAG_LEN] {
+    input(mac, sequence, key_id, payload)
+        .finalize()
+        .into_bytes()
+        .into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify_in_sequence() {
+        let mut signer = Signer::new(7, b"session key");
+        let mut verifier = Verifier::new().with_key(7, b"session key");
+        let mut frames = Vec::new();
+        for payload in [&b"one"[..], b"", b"three"] {
+            let mut frame = payload.to_vec();
+            signer.sign(payload, &mut frame);
+            assert_eq!(frame.len(), payload.len() + AUTH_LEN);
+            assert!(frame[payload.len()..].starts_with(b"07"));
+            frames.push(frame);
+        }
+        assert_eq!(verifier.verify(&frames[0]), Ok(&b"one"[..]));
+        //Replayed and reordered frames are out of sequence
+        assert_eq!(verifier.verify(&frames[0]), Err(AuthFailure::BadTag));
+        assert_eq!(verifier.verify(&frames[2]), Err(AuthFailure::BadTag));
+        assert_eq!(verifier.verify(&frames[1]), Ok(&b""[..]));
+        assert_eq!(verifier.verify(&frames[2]), Ok(&b"three"[..]));
+        assert_eq!(verifier.sequence(), 3);
+    }
+
+    #[test]
+    fn test_forged_and_malformed_frames() {
+        let mut signer = Signer::new(1, b"session key");
+        let mut verifier = Verifier::new().with_key(1, b"session key");
+        let mut frame = b"payload".to_vec();
+        signer.sign(b"payload", &mut frame);
+
+        let mut modified = frame.clone();
+        modified[0] ^= 1;
+        assert_eq!(verifier.verify(&modified), Err(AuthFailure::BadTag));
+        let mut forged = b"payload".to_vec();
+        Signer::new(1, b"guessed key").sign(b"payload", &mut forged);
+        assert_eq!(verifier.verify(&forged), Err(AuthFailure::BadTag));
+        assert_eq!(verifier.verify(b"payload"), Err(AuthFailure::Malformed));
+        let mut uppercase = frame.clone();
+        uppercase.make_ascii_uppercase();
+        assert_eq!(verifier.verify(&uppercase), Err(AuthFailure::Malformed));
+        //None of the failures used up the sequence number
+        assert_eq!(verifier.verify(&frame), Ok(&b"payload"[..]));
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let mut signer = Signer::new(1, b"old key");
+        let mut verifier = Verifier::new()
+            .with_key(1, b"old key")
+            .with_key(2, b"new key");
+        let mut first = b"first".to_vec();
+        signer.sign(b"first", &mut first);
+        signer.rotate(2, b"new key");
+        let mut second = b"second".to_vec();
+        signer.sign(b"second", &mut second);
+        let mut third = b"third".to_vec();
+        signer.sign(b"third", &mut third);
+
+        assert_eq!(verifier.verify(&first), Ok(&b"first"[..]));
+        assert_eq!(verifier.verify(&second), Ok(&b"second"[..]));
+        verifier.remove_key(2);
+        assert_eq!(verifier.verify(&third), Err(AuthFailure::UnknownKey(2)));
+    }
+}
@@ -1,19 +1,124 @@
 //! ## ByteMarker
 //!
-use crate::{Byte, ByteMarks, MARK, TAIL};
+use crate::{
+    Byte, ByteMarks, Checksum, ConstMarker, Error, Frame, MarkerConfig, NotMarked, Result, Segment,
+    Split, Terminator,
+};
+#[cfg(feature = "aead")]
+use crate::{Decryptor, Encryptor};
+#[cfg(feature = "hmac")]
+use crate::{Signer, Verifier};
+#[cfg(any(feature = "hmac", feature = "aead"))]
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Range;
 
-pub struct ByteMarker<'a> {
-    initializer: ByteMarks<'a>,
-    marks: ByteMarks<'a>,
-    tail: Option<ByteMarks<'a>>,
+/// Unmarked frames along with any trailing bytes that were not followed by a mark.
+pub type Unmarked<'b> = (Vec<&'b [Byte]>, Option<&'b [Byte]>);
+
+/// Unmarked frames grouped into tail terminated batches, along with any trailing
+/// bytes that were not followed by a mark.
+pub type Batches<'b> = (Vec<Vec<&'b [Byte]>>, Option<&'b [Byte]>);
+
+/// Decrypted frames along with any trailing bytes that were not followed by a mark.
+#[cfg(feature = "aead")]
+pub type Decrypted<'b> = (Vec<Vec<Byte>>, Option<&'b [Byte]>);
+
+/// Kind of demarcating byte pattern found in a byte stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Boundary {
+    Mark,
+    Tail,
+}
+
+/// Finds, adds and strips marks and tails.
+///
+/// The index tables used for matching are built once, by the constructor, or at
+/// compile time for a `ConstMarker` - clones share them, so a marker is cheap to
+/// clone and hand to other threads or readers.
+#[derive(Clone)]
+pub struct ByteMarker {
+    tables: Tables,
+}
+
+#[derive(Clone)]
+enum Tables {
+    Built(Arc<BuiltTables>),
+    Const(&'static ConstMarker),
+}
+
+struct BuiltTables {
+    initializer: ByteMarks,
+    marks: ByteMarks,
+    tail: Option<ByteMarks>,
+}
+
+impl BuiltTables {
+    fn find_boundary(&self, bytes: &[Byte], from: usize) -> Option<(usize, Boundary)> {
+        let start_byte = self.initializer.marking_start_byte();
+        (from..bytes.len()).find_map(|index| {
+            if bytes[index] == start_byte
+                && self
+                    .initializer
+                    .marking_matches(&self.initializer, &self.marks, index, bytes)
+            {
+                return Some((index, Boundary::Mark));
+            }
+            match self.tail {
+                Some(ref tail)
+                    if bytes[index] == self.initializer.tail_start_byte()
+                        && self.initializer.tail_marking_matches(
+                            &self.initializer,
+                            tail,
+                            index,
+                            bytes,
+                        ) =>
+                {
+                    Some((index, Boundary::Tail))
+                }
+                _ => None,
+            }
+        })
+    }
+}
+
+impl fmt::Debug for ByteMarker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ByteMarker")
+            .field("mark", &String::from_utf8_lossy(self.mark()))
+            .field("tail", &String::from_utf8_lossy(self.tail()))
+            .finish()
+    }
 }
 
-impl<'a> ByteMarker<'a> {
+impl ByteMarker {
+    /// Uses the built-in mark and tail - see `MarkerConfig::from_env` for picking
+    /// them up from the environment instead.
     pub fn with_defaults() -> Self {
-        Self::new(&MARK, &TAIL)
+        default_marker()
+    }
+
+    pub fn new(mark: &str, tail: &str) -> Self {
+        Self::from_bytes(mark.as_bytes(), tail.as_bytes())
+    }
+
+    /// Uses the mark and tail of `config`, which were validated when it was built.
+    pub fn from_config(config: &MarkerConfig) -> Self {
+        Self::from_bytes(config.mark(), config.tail())
     }
 
-    pub fn new(mark: &'a str, tail: &'a str) -> Self {
+    /// Matches with the tables of `marker`, precomputed at compile time - nothing
+    /// is built or allocated.
+    pub fn from_const(marker: &'static ConstMarker) -> Self {
+        Self {
+            tables: Tables::Const(marker),
+        }
+    }
+
+    fn from_bytes(mark: &[Byte], tail: &[Byte]) -> Self {
         let initializer = ByteMarks::initialize(mark, tail);
         let marks = initializer.init_marking_indices();
         let tail = if initializer.tail_bytes_len() > 0 {
@@ -22,66 +127,423 @@ impl<'a> ByteMarker<'a> {
             None
         };
         Self {
-            initializer,
-            marks,
-            tail,
+            tables: Tables::Built(Arc::new(BuiltTables {
+                initializer,
+                marks,
+                tail,
+            })),
         }
     }
 
-    pub fn unmark<'b>(&self, bytes: &'b [Byte]) -> Option<(Vec<&'b [Byte]>, Option<&'b [Byte]>)> {
+    pub fn unmark<'b>(&self, bytes: &'b [Byte]) -> Option<Unmarked<'b>> {
         if bytes.is_empty() {
             return None;
         }
-        let mut unmarked = Vec::with_capacity(bytes.len());
+        let mut unmarked = Vec::new();
+        let mut left_over = None;
+        for segment in self.split(bytes) {
+            match segment {
+                Segment::Frame(frame) | Segment::Tail(frame) => unmarked.push(frame),
+                Segment::LeftOver(remained) => left_over = Some(remained),
+            }
+        }
+        Some((unmarked, left_over))
+    }
+
+    /// Strips the marks out of `bytes` by shifting the payload left within the same
+    /// allocation, and returns where each frame now sits. Frames are contiguous - the
+    /// bytes after the last range are unterminated left over bytes, and anything
+    /// following the tail is dropped.
+    pub fn unmark_in_place(&self, bytes: &mut Vec<Byte>) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        let mut read = 0;
+        let mut write = 0;
+        while let Some((index, boundary)) = self.find_boundary(bytes, read) {
+            bytes.copy_within(read..index, write);
+            ranges.push(write..write + index - read);
+            write += index - read;
+            read = index + self.boundary_len(boundary);
+            if boundary == Boundary::Tail {
+                bytes.truncate(write);
+                return ranges;
+            }
+        }
+        let left_over = bytes.len() - read;
+        bytes.copy_within(read.., write);
+        bytes.truncate(write + left_over);
+        ranges
+    }
+
+    /// Lazily splits `bytes` at each mark, without allocating. Splitting stops after
+    /// the segment ended by the tail, and any trailing bytes followed by neither come
+    /// last as `Segment::LeftOver`.
+    pub fn split<'s, 'b>(&'s self, bytes: &'b [Byte]) -> Split<'s, 'b> {
+        Split::new(self, bytes)
+    }
+
+    /// Strict counterpart of `unmark` - trailing bytes not followed by a mark or tail
+    /// mean the input was cut short, and are reported as `Error::Truncated`.
+    pub fn try_unmark<'b>(&self, bytes: &'b [Byte]) -> Result<Vec<&'b [Byte]>> {
+        match self.unmark(bytes) {
+            None => Ok(Vec::new()),
+            Some((unmarked, None)) => Ok(unmarked),
+            Some((_, Some(left_over))) => Err(Error::Truncated {
+                bytes: left_over.len(),
+            }),
+        }
+    }
+
+    /// Like `unmark`, but every frame ended by a mark or the tail carries a `checksum`,
+    /// which is verified and stripped - see `mark_checked`. The first frame that does
+    /// not match fails with `Error::ChecksumMismatch`, giving its offset into `bytes`.
+    /// Trailing bytes not followed by a mark are left over as they are.
+    pub fn unmark_checked<'b>(
+        &self,
+        bytes: &'b [Byte],
+        checksum: Checksum,
+    ) -> Result<Unmarked<'b>> {
+        let mut unmarked = Vec::new();
+        let mut left_over = None;
+        for frame in self.unmark_frames(bytes) {
+            if frame.terminator == Terminator::Eof {
+                left_over = Some(frame.data);
+                continue;
+            }
+            let payload = checksum.verify(frame.data).ok_or(Error::ChecksumMismatch {
+                frame_index: unmarked.len(),
+                offset: frame.start_offset,
+            })?;
+            unmarked.push(payload);
+        }
+        Ok((unmarked, left_over))
+    }
+
+    /// Like `unmark_checked`, but every frame ended by a mark or the tail is checked
+    /// by `verifier` as the next frame of the session - see `mark_signed`. The first
+    /// frame that fails is reported as `Error::Unauthenticated`.
+    #[cfg(feature = "hmac")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "hmac")))]
+    pub fn unmark_verified<'b>(
+        &self,
+        bytes: &'b [Byte],
+        verifier: &mut Verifier,
+    ) -> Result<Unmarked<'b>> {
+        self.check_hex_safe()?;
+        let mut unmarked = Vec::new();
+        let mut left_over = None;
+        for frame in self.unmark_frames(bytes) {
+            if frame.terminator == Terminator::Eof {
+                left_over = Some(frame.data);
+                continue;
+            }
+            let payload = verifier
+                .verify(frame.data)
+                .map_err(|reason| Error::Unauthenticated {
+                    frame_index: unmarked.len(),
+                    offset: frame.start_offset,
+                    reason,
+                })?;
+            unmarked.push(payload);
+        }
+        Ok((unmarked, left_over))
+    }
+
+    /// Like `unmark`, but every frame ended by a mark or the tail is decrypted by
+    /// `decryptor` as the next frame of the stream - see `mark_encrypted`. The first
+    /// frame that does not decrypt is reported as `Error::Undecryptable`.
+    #[cfg(feature = "aead")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "aead")))]
+    pub fn unmark_decrypted<'b>(
+        &self,
+        bytes: &'b [Byte],
+        decryptor: &mut Decryptor,
+    ) -> Result<Decrypted<'b>> {
+        self.check_hex_safe()?;
+        let mut unmarked = Vec::new();
+        let mut left_over = None;
+        for frame in self.unmark_frames(bytes) {
+            if frame.terminator == Terminator::Eof {
+                left_over = Some(frame.data);
+                continue;
+            }
+            let payload = decryptor
+                .decrypt(frame.data)
+                .map_err(|_| Error::Undecryptable {
+                    frame_index: unmarked.len(),
+                    offset: frame.start_offset,
+                })?;
+            unmarked.push(payload.to_vec());
+        }
+        Ok((unmarked, left_over))
+    }
+
+    /// Like `unmark`, but carries on past each tail instead of stopping at the first one.
+    /// Frames after the last tail that are followed by a mark form a final batch.
+    pub fn unmark_batches<'b>(&self, bytes: &'b [Byte]) -> Option<Batches<'b>> {
+        if bytes.is_empty() {
+            return None;
+        }
+        let mut batches = Vec::new();
+        let mut batch = Vec::new();
         let mut processed_bytes = 0;
 
-        let start_byte = self.initializer.marking_start_byte();
-        let mark_size = self.initializer.marking_bytes_len();
+        while let Some((index, boundary)) = self.find_boundary(bytes, processed_bytes) {
+            batch.push(&bytes[processed_bytes..index]);
+            processed_bytes = index + self.boundary_len(boundary);
+            if boundary == Boundary::Tail {
+                batches.push(core::mem::take(&mut batch));
+            }
+        }
+        if !batch.is_empty() {
+            batches.push(batch);
+        }
+        let left_over = Some(&bytes[processed_bytes..]).filter(|remained| !remained.is_empty());
+        Some((batches, left_over))
+    }
 
-        for index in 0..bytes.len() {
-            if bytes[index] == start_byte
-                && self
-                    .initializer
-                    .marking_matches(&self.initializer, &self.marks, index, bytes)
-            {
-                unmarked.push(&bytes[processed_bytes..index]);
-                processed_bytes = index + mark_size;
+    /// Like `unmark`, but each frame records its offset into `bytes` and what ended it.
+    /// Trailing bytes not followed by a mark come last, with `Terminator::Eof`.
+    pub fn unmark_frames<'b>(&self, bytes: &'b [Byte]) -> Vec<Frame<&'b [Byte]>> {
+        let mut frames = Vec::new();
+        let mut processed_bytes = 0;
+
+        while let Some((index, boundary)) = self.find_boundary(bytes, processed_bytes) {
+            frames.push(Frame {
+                data: &bytes[processed_bytes..index],
+                start_offset: processed_bytes,
+                terminator: boundary.into(),
+            });
+            processed_bytes = index + self.boundary_len(boundary);
+            if boundary == Boundary::Tail {
+                return frames;
             }
-            if let Some(ref tail) = self.tail {
-                if bytes[index] == self.initializer.tail_start_byte()
-                    && self
-                        .initializer
-                        .tail_marking_matches(&self.initializer, tail, index, bytes)
-                {
-                    unmarked.push(&bytes[processed_bytes..index]);
-                    return Some((unmarked, None));
+        }
+        if processed_bytes < bytes.len() {
+            frames.push(Frame {
+                data: &bytes[processed_bytes..],
+                start_offset: processed_bytes,
+                terminator: Terminator::Eof,
+            });
+        }
+        frames
+    }
+
+    //Position of the first mark or tail in `bytes` at or after `from`
+    pub(crate) fn find_boundary(&self, bytes: &[Byte], from: usize) -> Option<(usize, Boundary)> {
+        match &self.tables {
+            Tables::Built(built) => built.find_boundary(bytes, from),
+            Tables::Const(marker) => (from..bytes.len()).find_map(|index| {
+                if marker.mark_at(bytes, index) {
+                    Some((index, Boundary::Mark))
+                } else if marker.tail_at(bytes, index) {
+                    Some((index, Boundary::Tail))
+                } else {
+                    None
                 }
+            }),
+        }
+    }
+
+    pub(crate) fn boundary_len(&self, boundary: Boundary) -> usize {
+        match boundary {
+            Boundary::Mark => self.mark().len(),
+            Boundary::Tail => self.tail().len(),
+        }
+    }
+
+    pub(crate) fn mark(&self) -> &[Byte] {
+        match &self.tables {
+            Tables::Built(built) => built.initializer.marking_bytes(),
+            Tables::Const(marker) => marker.mark(),
+        }
+    }
+
+    pub(crate) fn tail(&self) -> &[Byte] {
+        match &self.tables {
+            Tables::Built(built) => built.initializer.tail_bytes(),
+            Tables::Const(marker) => marker.tail(),
+        }
+    }
+
+    //Signed and encrypted frames end in lowercase hex digits. A mark or tail that
+    //starts or ends with one could be made up of those digits and the bytes around
+    //them, and split a frame where it was never marked
+    #[cfg(any(feature = "hmac", feature = "aead"))]
+    pub(crate) fn check_hex_safe(&self) -> Result<()> {
+        let is_hex = |byte: &Byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(byte);
+        for (name, bytes) in [("Mark", self.mark()), ("Tail", self.tail())] {
+            if bytes.first().is_some_and(is_hex) || bytes.last().is_some_and(is_hex) {
+                return Err(Error::InvalidConfig(format!(
+                    "{} {:?} starts or ends with a lowercase hex digit",
+                    name,
+                    String::from_utf8_lossy(bytes)
+                )));
             }
         }
-        let left_over = match &bytes[processed_bytes..] {
-            [remained @ ..] if !remained.is_empty() => Some(remained),
-            _ => None,
-        };
-        Some((unmarked, left_over))
+        Ok(())
+    }
+
+    //Longest run of trailing bytes that could still turn out to be a mark or tail
+    pub(crate) fn partial_len(&self) -> usize {
+        self.mark().len().max(self.tail().len()) - 1
     }
 
     pub fn mark_bytes(&self, bytes: &mut Vec<Byte>) {
-        bytes.extend(self.initializer.marking_bytes());
+        bytes.extend(self.mark());
     }
 
+    /// Appends `payload` followed by the mark to `marked`, growing it at most once.
+    pub fn mark_into(&self, marked: &mut Vec<Byte>, payload: &[Byte]) {
+        marked.reserve(payload.len() + self.mark().len());
+        marked.extend_from_slice(payload);
+        marked.extend_from_slice(self.mark());
+    }
+
+    /// Like `mark_into`, but with the `checksum` of `payload` between it and the mark.
+    pub fn mark_checked(&self, marked: &mut Vec<Byte>, payload: &[Byte], checksum: Checksum) {
+        marked.reserve(payload.len() + checksum.encoded_len() + self.mark().len());
+        marked.extend_from_slice(payload);
+        checksum.append(payload, marked);
+        marked.extend_from_slice(self.mark());
+    }
+
+    /// Like `mark_into`, but with the key id and tag `signer` gives `payload` between
+    /// it and the mark. Frames must be read back in the order they were signed. Fails
+    /// with `Error::InvalidConfig`, writing nothing, if the mark or tail starts or
+    /// ends with a lowercase hex digit.
+    #[cfg(feature = "hmac")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "hmac")))]
+    pub fn mark_signed(
+        &self,
+        marked: &mut Vec<Byte>,
+        payload: &[Byte],
+        signer: &mut Signer,
+    ) -> Result<()> {
+        self.check_hex_safe()?;
+        marked.reserve(payload.len() + crate::AUTH_LEN + self.mark().len());
+        marked.extend_from_slice(payload);
+        signer.sign(payload, marked);
+        marked.extend_from_slice(self.mark());
+        Ok(())
+    }
+
+    /// Like `mark_into`, but `payload` is sealed by `encryptor` as the next frame of
+    /// the stream and written as hex. Fails with `Error::InvalidConfig`, writing
+    /// nothing, if the mark or tail starts or ends with a lowercase hex digit.
+    #[cfg(feature = "aead")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "aead")))]
+    pub fn mark_encrypted(
+        &self,
+        marked: &mut Vec<Byte>,
+        payload: &[Byte],
+        encryptor: &mut Encryptor,
+    ) -> Result<()> {
+        self.check_hex_safe()?;
+        marked.reserve(2 * payload.len() + crate::SEAL_LEN + self.mark().len());
+        encryptor.encrypt(payload, marked);
+        marked.extend_from_slice(self.mark());
+        Ok(())
+    }
+
+    /// Marks every payload and concatenates them into a buffer allocated once, at
+    /// its exact final size. With `with_tail`, the last payload is followed by the
+    /// tail instead of the mark - `msg|mark|msg|mark|msg|tail` - or by the mark if
+    /// there is no tail. No payloads make no bytes, tail or not.
+    pub fn mark_all<I>(&self, payloads: I, with_tail: bool) -> Vec<Byte>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[Byte]>,
+    {
+        //Only the payload handles are collected - their bytes are copied just once
+        let payloads: Vec<I::Item> = payloads.into_iter().collect();
+        let mark = self.mark();
+        let last = match self.tail() {
+            tail if with_tail && !tail.is_empty() => tail,
+            _ => mark,
+        };
+        let payload_len: usize = payloads.iter().map(|payload| payload.as_ref().len()).sum();
+        let capacity = match payloads.len() {
+            0 => 0,
+            count => payload_len + mark.len() * (count - 1) + last.len(),
+        };
+
+        let mut marked = Vec::with_capacity(capacity);
+        for (index, payload) in payloads.iter().enumerate() {
+            marked.extend_from_slice(payload.as_ref());
+            if index + 1 == payloads.len() {
+                marked.extend_from_slice(last);
+            } else {
+                marked.extend_from_slice(mark);
+            }
+        }
+        marked
+    }
+
+    /// Drops as many trailing bytes as the mark is long, without looking at them.
+    /// See `strip_mark` for a checked alternative.
     pub fn erase_mark(&self, bytes: &mut Vec<Byte>) {
-        bytes.truncate(bytes.len() - self.initializer.marking_bytes_len());
+        bytes.truncate(bytes.len() - self.mark().len());
     }
 
     pub fn mark_tail(&self, bytes: &mut Vec<Byte>) {
-        bytes.extend(self.initializer.tail_bytes());
+        bytes.extend(self.tail());
     }
 
+    /// Drops as many trailing bytes as the tail is long, without looking at them.
+    /// See `strip_tail` for a checked alternative.
     pub fn erase_tail(&self, bytes: &mut Vec<Byte>) {
-        bytes.truncate(bytes.len() - self.initializer.tail_bytes_len());
+        bytes.truncate(bytes.len() - self.tail().len());
+    }
+
+    /// Removes the mark from the end of `bytes`. Leaves `bytes` untouched and fails
+    /// with `NotMarked` if they do not end with the mark.
+    pub fn strip_mark(&self, bytes: &mut Vec<Byte>) -> core::result::Result<(), NotMarked> {
+        Self::strip_suffix(bytes, self.mark())
+    }
+
+    /// Removes the tail from the end of `bytes`. Leaves `bytes` untouched and fails
+    /// with `NotMarked` if they do not end with the tail, or no tail is configured.
+    pub fn strip_tail(&self, bytes: &mut Vec<Byte>) -> core::result::Result<(), NotMarked> {
+        Self::strip_suffix(bytes, self.tail())
+    }
+
+    /// Repeatedly removes trailing marks and tails, in whatever order they were
+    /// appended. Fails with `NotMarked` only if there was nothing to remove.
+    pub fn strip_all(&self, bytes: &mut Vec<Byte>) -> core::result::Result<(), NotMarked> {
+        let mut stripped = false;
+        while self.strip_tail(bytes).is_ok() || self.strip_mark(bytes).is_ok() {
+            stripped = true;
+        }
+        if stripped {
+            Ok(())
+        } else {
+            Err(NotMarked)
+        }
+    }
+
+    fn strip_suffix(bytes: &mut Vec<Byte>, suffix: &[Byte]) -> core::result::Result<(), NotMarked> {
+        if suffix.is_empty() || !bytes.ends_with(suffix) {
+            return Err(NotMarked);
+        }
+        bytes.truncate(bytes.len() - suffix.len());
+        Ok(())
     }
 
     pub fn concat_byte(first: &[Byte], second: &[Byte]) -> Vec<Byte> {
         [first, second].concat()
     }
 }
+
+//Built once and shared by clones, where there is somewhere to keep it
+#[cfg(feature = "std")]
+fn default_marker() -> ByteMarker {
+    static DEFAULT: std::sync::OnceLock<ByteMarker> = std::sync::OnceLock::new();
+    DEFAULT
+        .get_or_init(|| ByteMarker::from_config(&MarkerConfig::default()))
+        .clone()
+}
+
+#[cfg(not(feature = "std"))]
+fn default_marker() -> ByteMarker {
+    ByteMarker::from_config(&MarkerConfig::default())
+}
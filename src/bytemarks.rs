@@ -2,22 +2,26 @@
 //!
 //!
 
-use crate::Byte;
-use std::collections::HashMap;
+use crate::{Byte, Error, Result};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 
+#[allow(clippy::empty_line_after_doc_comments)]
 /// An enum to represent demarcating byte pattern.
 
-pub enum ByteMarks<'a> {
-    Initializer(&'a [Byte], &'a [Byte]),
-    TailIndices(Option<HashMap<&'a Byte, usize>>),
+pub enum ByteMarks {
+    Initializer(Vec<Byte>, Vec<Byte>),
+    TailIndices(Option<BTreeMap<Byte, usize>>),
     Marking(Byte),
     MarkingEnd,
     TailEnd,
-    MarkingIndices(Option<HashMap<&'a Byte, usize>>),
+    MarkingIndices(Option<BTreeMap<Byte, usize>>),
 }
 
-impl std::fmt::Debug for ByteMarks<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Debug for ByteMarks {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Initializer(mark, tail) => write!(
                 f,
@@ -34,41 +38,45 @@ impl std::fmt::Debug for ByteMarks<'_> {
     }
 }
 
-impl<'a> From<Byte> for ByteMarks<'a> {
-    fn from(byte: Byte) -> ByteMarks<'a> {
+impl From<Byte> for ByteMarks {
+    fn from(byte: Byte) -> ByteMarks {
         Marking(byte)
     }
 }
 
 use crate::ByteMarks::*;
 
-impl<'a> ByteMarks<'a> {
-    pub(crate) fn initialize(mark: &'a str, tail: &'a str) -> Self {
-        assert_ne!(mark, "", "Mark should not be empty!");
-        assert_ne!(
-            mark, tail,
-            "Mark {} and tail {} same! Should not be",
-            mark, tail
-        );
-        let unique = Self::all_marking_unique(mark);
-        let msg = unique.map(|(i, j, c)| {
-            format!(
-                "Mark contains duplicate character {} at indices {} and {}",
-                c, i, j
-            )
-        });
-        assert!(unique == None, "{}", msg.unwrap());
-
-        let unique = Self::all_marking_unique(tail);
-        let msg = unique.map(|(i, j, c)| {
-            format!(
-                "Tail contains duplicate character {} at indices {} and {}",
-                c, i, j
-            )
-        });
-        assert!(unique == None, "{}", msg.unwrap());
+impl ByteMarks {
+    pub(crate) fn initialize(mark: &[Byte], tail: &[Byte]) -> Self {
+        if let Err(err) = Self::validate(mark, tail) {
+            panic!("{}", err);
+        }
+        ByteMarks::Initializer(mark.to_vec(), tail.to_vec())
+    }
 
-        ByteMarks::Initializer(mark.as_bytes(), tail.as_bytes())
+    /// Checks that `mark` and `tail` can demarcate bytes - the mark must not be empty
+    /// or the same as the tail, and neither may repeat a byte.
+    pub(crate) fn validate(mark: &[Byte], tail: &[Byte]) -> Result<()> {
+        let invalid = |reason: String| Err(Error::InvalidConfig(reason));
+        if mark.is_empty() {
+            return invalid("Mark should not be empty!".to_string());
+        }
+        if mark == tail {
+            return invalid(format!(
+                "Mark {:?} and tail {:?} same! Should not be",
+                String::from_utf8_lossy(mark),
+                String::from_utf8_lossy(tail)
+            ));
+        }
+        for (name, bytes) in [("Mark", mark), ("Tail", tail)] {
+            if let Some((i, j, byte)) = Self::duplicate_byte(bytes) {
+                return invalid(format!(
+                    "{} contains duplicate byte {:#04x} at indices {} and {}",
+                    name, byte, i, j
+                ));
+            }
+        }
+        Ok(())
     }
 
     pub fn all_marking_unique(s: &str) -> Option<(usize, usize, char)> {
@@ -80,12 +88,23 @@ impl<'a> ByteMarks<'a> {
                 .map(|(j, _)| (i, j, c))
         })
     }
+
+    //Matching goes by byte, so a byte repeated within a multi byte character counts too
+    fn duplicate_byte(bytes: &[Byte]) -> Option<(usize, usize, Byte)> {
+        bytes.iter().enumerate().find_map(|(i, byte)| {
+            bytes[i + 1..]
+                .iter()
+                .position(|other| other == byte)
+                .map(|j| (i, i + 1 + j, *byte))
+        })
+    }
+
     pub(crate) fn init_marking_indices(&self) -> Self {
         match self {
             ByteMarks::Initializer(marking_bytes, _) => {
-                let mut marking_byte_indices = HashMap::new();
-                for i in 0..marking_bytes.len() {
-                    marking_byte_indices.insert(&marking_bytes[i], i);
+                let mut marking_byte_indices = BTreeMap::new();
+                for (i, byte) in marking_bytes.iter().enumerate() {
+                    marking_byte_indices.insert(*byte, i);
                 }
                 ByteMarks::MarkingIndices(Some(marking_byte_indices))
             }
@@ -96,9 +115,9 @@ impl<'a> ByteMarks<'a> {
     pub(crate) fn init_tail_indices(&self) -> Self {
         match self {
             ByteMarks::Initializer(_, tail_bytes) => {
-                let mut tail_byte_indices = HashMap::new();
-                for i in 0..tail_bytes.len() {
-                    tail_byte_indices.insert(&tail_bytes[i], i);
+                let mut tail_byte_indices = BTreeMap::new();
+                for (i, byte) in tail_bytes.iter().enumerate() {
+                    tail_byte_indices.insert(*byte, i);
                 }
                 ByteMarks::TailIndices(Some(tail_byte_indices))
             }
@@ -176,11 +195,7 @@ impl<'a> ByteMarks<'a> {
         }
     }
 
-    fn next_marking(
-        &'a self,
-        initializer: &'a ByteMarks<'a>,
-        indices: &'a ByteMarks<'a>,
-    ) -> Option<Self> {
+    fn next_marking(&self, initializer: &ByteMarks, indices: &ByteMarks) -> Option<Self> {
         match self {
             ByteMarks::Initializer(_, _) => Some(initializer.marking_start_byte().into()),
             Marking(byte) if *byte == initializer.marking_end_byte() => Some(MarkingEnd),
@@ -196,10 +211,11 @@ impl<'a> ByteMarks<'a> {
         }
     }
 
+    #[allow(clippy::unnecessary_map_or)]
     pub(crate) fn marking_matches(
-        &'a self,
-        initializer: &'a ByteMarks<'a>,
-        indices: &'a ByteMarks<'a>,
+        &self,
+        initializer: &ByteMarks,
+        indices: &ByteMarks,
         index: usize,
         bytes: &[u8],
     ) -> bool {
@@ -235,11 +251,7 @@ impl<'a> ByteMarks<'a> {
         }
     }
 
-    fn next_tail_marking(
-        &'a self,
-        initializer: &'a ByteMarks<'a>,
-        indices: &'a ByteMarks<'a>,
-    ) -> Option<Self> {
+    fn next_tail_marking(&self, initializer: &ByteMarks, indices: &ByteMarks) -> Option<Self> {
         match self {
             ByteMarks::Initializer(_, _) => Some(initializer.tail_start_byte().into()),
             Marking(byte) if *byte == initializer.tail_end_byte() => Some(TailEnd),
@@ -255,10 +267,11 @@ impl<'a> ByteMarks<'a> {
         }
     }
 
+    #[allow(clippy::unnecessary_map_or)]
     pub(crate) fn tail_marking_matches(
-        &'a self,
-        initializer: &'a ByteMarks<'a>,
-        indices: &'a ByteMarks<'a>,
+        &self,
+        initializer: &ByteMarks,
+        indices: &ByteMarks,
         index: usize,
         bytes: &[u8],
     ) -> bool {
@@ -0,0 +1,133 @@
+//! ## Checksum
+//!
+//! Optional per frame integrity check. The checksum of the payload is written
+//! between the payload and the mark as fixed width lowercase hex digits, so that
+//! it can never contain a byte outside `0-9a-f`.
+
+use crate::{hex, Byte};
+use alloc::vec::Vec;
+
+static CRC16_TABLE: [u32; 256] = table(0xA001);
+static CRC32_TABLE: [u32; 256] = table(0xEDB8_8320);
+static CRC32C_TABLE: [u32; 256] = table(0x82F6_3B78);
+
+/// Checksum carried by each frame, between the payload and the mark or tail.
+///
+/// The digits are scanned for the mark like the rest of the frame, so a mark or
+/// tail ending in a lowercase hex digit could be found ending inside them. Any
+/// other mark cannot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Checksum {
+    /// CRC-16/MODBUS, written as 4 hex digits.
+    Crc16,
+    /// CRC-32 (IEEE 802.3), written as 8 hex digits.
+    Crc32,
+    /// CRC-32C (Castagnoli), written as 8 hex digits.
+    Crc32c,
+}
+
+impl Checksum {
+    pub fn compute(self, bytes: &[Byte]) -> u32 {
+        match self {
+            Checksum::Crc16 => crc(&CRC16_TABLE, 0xFFFF, bytes),
+            Checksum::Crc32 => !crc(&CRC32_TABLE, !0, bytes),
+            Checksum::Crc32c => !crc(&CRC32C_TABLE, !0, bytes),
+        }
+    }
+
+    /// Number of bytes the checksum takes up in a frame.
+    pub const fn encoded_len(self) -> usize {
+        match self {
+            Checksum::Crc16 => 4,
+            Checksum::Crc32 | Checksum::Crc32c => 8,
+        }
+    }
+
+    /// Appends the checksum of `payload` to `out`.
+    pub fn append(self, payload: &[Byte], out: &mut Vec<Byte>) {
+        out.extend_from_slice(&self.digits(payload)[8 - self.encoded_len()..]);
+    }
+
+    /// Splits the checksum off the end of `frame`, returning the payload if the
+    /// checksum is there and matches it.
+    pub fn verify(self, frame: &[Byte]) -> Option<&[Byte]> {
+        let split = frame.len().checked_sub(self.encoded_len())?;
+        let (payload, digits) = frame.split_at(split);
+        (digits == &self.digits(payload)[8 - self.encoded_len()..]).then_some(payload)
+    }
+
+    //Hex digits of the checksum of `payload`, zero padded to 8
+    fn digits(self, payload: &[Byte]) -> [Byte; 8] {
+        let checksum = self.compute(payload);
+        let mut digits = [0; 8];
+        for (i, digit) in digits.iter_mut().enumerate() {
+            *digit = hex::DIGITS[(checksum >> (28 - 4 * i)) as usize & 0xf];
+        }
+        digits
+    }
+}
+
+//Reflected CRC, a byte at a time
+fn crc(table: &[u32; 256], init: u32, bytes: &[Byte]) -> u32 {
+    bytes.iter().fold(init, |crc, byte| {
+        table[((crc ^ *byte as u32) & 0xff) as usize] ^ crc >> 8
+    })
+}
+
+const fn table(polynomial: u32) -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                crc >> 1 ^ polynomial
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_values() {
+        assert_eq!(Checksum::Crc16.compute(b"123456789"), 0x4B37);
+        assert_eq!(Checksum::Crc32.compute(b"123456789"), 0xCBF4_3926);
+        assert_eq!(Checksum::Crc32c.compute(b"123456789"), 0xE306_9283);
+        assert_eq!(Checksum::Crc32.compute(b""), 0);
+    }
+
+    #[test]
+    fn test_append_and_verify() {
+        let mut frame = b"123456789".to_vec();
+        Checksum::Crc32.append(b"123456789", &mut frame);
+        assert_eq!(frame, b"123456789cbf43926");
+        assert_eq!(Checksum::Crc32.verify(&frame), Some(&b"123456789"[..]));
+        assert_eq!(Checksum::Crc32c.verify(&frame), None);
+
+        let mut frame = Vec::new();
+        Checksum::Crc16.append(b"", &mut frame);
+        assert_eq!(frame, b"ffff");
+        assert_eq!(Checksum::Crc16.verify(&frame), Some(&b""[..]));
+
+        frame[0] ^= 1;
+        assert_eq!(Checksum::Crc16.verify(&frame), None);
+        assert_eq!(Checksum::Crc16.verify(b"fff"), None);
+        //Only the digits `append` writes are accepted
+        assert_eq!(Checksum::Crc16.verify(b"FFFF"), None);
+    }
+}
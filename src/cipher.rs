@@ -0,0 +1,242 @@
+//! ## Encrypted frames
+//!
+//! Per frame AEAD encryption, for confidentiality without a TLS session - file
+//! archives, local IPC and the like. Each payload is sealed on its own and written
+//! as lowercase hex digits, ciphertext and authentication tag together, so that
+//! it cannot run into a mark or tail - as long as neither starts nor ends with
+//! such a digit, which is checked before a frame is sealed or opened:
+//!
+//! `hex(ciphertext|tag)|mark`
+//!
+//! The nonce is the stream id followed by the frame's position in the stream, and
+//! is never sent. Frames only open in the order they were sealed - replayed,
+//! reordered or dropped frames fail just like modified ones.
+
+use crate::{hex, Byte};
+use aes_gcm::Aes256Gcm;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+use chacha20poly1305::ChaCha20Poly1305;
+use core::fmt;
+
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// Number of bytes the tag adds to an encrypted frame - on top of the payload
+/// taking up twice its length once written as hex.
+pub const SEAL_LEN: usize = 2 * TAG_LEN;
+
+/// AEAD cipher frames are sealed with. Both take 256 bit keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cipher {
+    ChaCha20Poly1305,
+    Aes256Gcm,
+}
+
+/// A frame did not decrypt - it was modified, sealed with another key or stream
+/// id, or is out of order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecryptionFailed;
+
+impl fmt::Display for DecryptionFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Frame does not decrypt")
+    }
+}
+
+impl core::error::Error for DecryptionFailed {}
+
+//Cipher instance set up with its key
+#[derive(Clone)]
+enum Keyed {
+    ChaCha20Poly1305(ChaCha20Poly1305),
+    //The expanded AES key schedule is large
+    Aes256Gcm(Box<Aes256Gcm>),
+}
+
+impl Keyed {
+    fn new(cipher: Cipher, key: &[Byte; 32]) -> Self {
+        match cipher {
+            Cipher::ChaCha20Poly1305 => Keyed::ChaCha20Poly1305(ChaCha20Poly1305::new(key.into())),
+            Cipher::Aes256Gcm => Keyed::Aes256Gcm(Box::new(Aes256Gcm::new(key.into()))),
+        }
+    }
+
+    fn encrypt(&self, nonce: &[Byte; NONCE_LEN], buffer: &mut Vec<Byte>) {
+        match self {
+            Keyed::ChaCha20Poly1305(cipher) => cipher.encrypt_in_place(nonce.into(), b"", buffer),
+            Keyed::Aes256Gcm(cipher) => cipher.encrypt_in_place(nonce.into(), b"", buffer),
+        }
+        .expect("Vec buffers grow to fit the tag")
+    }
+
+    fn decrypt(&self, nonce: &[Byte; NONCE_LEN], buffer: &mut Vec<Byte>) -> Option<()> {
+        match self {
+            Keyed::ChaCha20Poly1305(cipher) => cipher.decrypt_in_place(nonce.into(), b"", buffer),
+            Keyed::Aes256Gcm(cipher) => cipher.decrypt_in_place(nonce.into(), b"", buffer),
+        }
+        .ok()
+    }
+}
+
+/// Seals the frames of one stream, in order - see `ByteMarker::mark_encrypted`.
+///
+/// Nonces restart with every `Encryptor`, so the same key and `stream_id` must
+/// never seal two streams. Use a fresh key per session, or a distinct stream id
+/// per stream under a long lived key.
+#[derive(Clone)]
+pub struct Encryptor {
+    cipher: Keyed,
+    stream_id: u32,
+    counter: u64,
+    buffer: Vec<Byte>,
+}
+
+impl Encryptor {
+    pub fn new(cipher: Cipher, key: &[Byte; 32], stream_id: u32) -> Self {
+        Self {
+            cipher: Keyed::new(cipher, key),
+            stream_id,
+            counter: 0,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Position of the next frame in the stream.
+    pub fn counter(&self) -> u64 {
+        self.counter
+    }
+
+    /// Appends `payload`, sealed as the next frame of the stream, to `out`.
+    pub fn encrypt(&mut self, payload: &[Byte], out: &mut Vec<Byte>) {
+        self.buffer.clear();
+        self.buffer.extend_from_slice(payload);
+        self.cipher
+            .encrypt(&nonce(self.stream_id, self.counter), &mut self.buffer);
+        self.counter += 1;
+        hex::push(&self.buffer, out);
+    }
+}
+
+impl fmt::Debug for Encryptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Encryptor")
+            .field("stream_id", &self.stream_id)
+            .field("counter", &self.counter)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Opens the frames of one stream, in the order they were sealed.
+///
+/// A frame that fails does not move the counter on, so frames slipped into the
+/// stream do not stop the genuine ones that follow from decrypting.
+#[derive(Clone)]
+pub struct Decryptor {
+    cipher: Keyed,
+    stream_id: u32,
+    counter: u64,
+    buffer: Vec<Byte>,
+}
+
+impl Decryptor {
+    pub fn new(cipher: Cipher, key: &[Byte; 32], stream_id: u32) -> Self {
+        Self {
+            cipher: Keyed::new(cipher, key),
+            stream_id,
+            counter: 0,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Position of the next frame in the stream.
+    pub fn counter(&self) -> u64 {
+        self.counter
+    }
+
+    /// Decrypts `frame` as the next frame of the stream. The payload is written to
+    /// a buffer that is reused by the next call.
+    pub fn decrypt(&mut self, frame: &[Byte]) -> Result<&[Byte], DecryptionFailed> {
+        self.buffer.clear();
+        self.buffer.resize(frame.len() / 2, 0);
+        hex::decode(frame, &mut self.buffer).ok_or(DecryptionFailed)?;
+        self.cipher
+            .decrypt(&nonce(self.stream_id, self.counter), &mut self.buffer)
+            .ok_or(DecryptionFailed)?;
+        self.counter += 1;
+        Ok(&self.buffer)
+    }
+}
+
+impl fmt::Debug for Decryptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Decryptor")
+            .field("stream_id", &self.stream_id)
+            .field("counter", &self.counter)
+            .finish_non_exhaustive()
+    }
+}
+
+fn nonce(stream_id: u32, counter: u64) -> [Byte; NONCE_LEN] {
+    let mut nonce = [0; NONCE_LEN];
+    nonce[..4].copy_from_slice(&stream_id.to_be_bytes());
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    nonce
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KEY: &[Byte; 32] = b"0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn test_round_trip_in_order() {
+        for cipher in [Cipher::ChaCha20Poly1305, Cipher::Aes256Gcm] {
+            let mut encryptor = Encryptor::new(cipher, KEY, 1);
+            let mut decryptor = Decryptor::new(cipher, KEY, 1);
+            let frames: Vec<Vec<Byte>> = [&b"one"[..], b"", b"one"]
+                .iter()
+                .map(|payload| {
+                    let mut frame = Vec::new();
+                    encryptor.encrypt(payload, &mut frame);
+                    assert_eq!(frame.len(), 2 * payload.len() + SEAL_LEN);
+                    assert!(frame.iter().all(|byte| hex::DIGITS.contains(byte)));
+                    frame
+                })
+                .collect();
+            //The same payload seals differently at each position
+            assert_ne!(frames[0], frames[2]);
+
+            assert_eq!(decryptor.decrypt(&frames[0]), Ok(&b"one"[..]));
+            //Replayed and reordered frames do not open
+            assert_eq!(decryptor.decrypt(&frames[0]), Err(DecryptionFailed));
+            assert_eq!(decryptor.decrypt(&frames[2]), Err(DecryptionFailed));
+            assert_eq!(decryptor.decrypt(&frames[1]), Ok(&b""[..]));
+            assert_eq!(decryptor.decrypt(&frames[2]), Ok(&b"one"[..]));
+            assert_eq!(decryptor.counter(), 3);
+        }
+    }
+
+    #[test]
+    fn test_tampered_frames() {
+        let mut encryptor = Encryptor::new(Cipher::ChaCha20Poly1305, KEY, 1);
+        let mut frame = Vec::new();
+        encryptor.encrypt(b"secret", &mut frame);
+        assert!(!frame.windows(6).any(|window| window == b"secret"));
+
+        let mut decryptor = Decryptor::new(Cipher::ChaCha20Poly1305, KEY, 1);
+        let mut modified = frame.clone();
+        modified[0] = if modified[0] == b'0' { b'1' } else { b'0' };
+        assert_eq!(decryptor.decrypt(&modified), Err(DecryptionFailed));
+        assert_eq!(decryptor.decrypt(b"not hex"), Err(DecryptionFailed));
+        for mut other in [
+            Decryptor::new(Cipher::ChaCha20Poly1305, KEY, 2),
+            Decryptor::new(Cipher::Aes256Gcm, KEY, 1),
+        ] {
+            assert_eq!(other.decrypt(&frame), Err(DecryptionFailed));
+        }
+        assert_eq!(decryptor.decrypt(&frame), Ok(&b"secret"[..]));
+    }
+}
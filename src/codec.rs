@@ -0,0 +1,271 @@
+//! ## MarkCodec
+//!
+//! A [tokio_util::codec] `Decoder`/`Encoder` pair for marked bytes - the same
+//! mark/tail semantics as `ByteMarker`, over a `FramedRead`/`FramedWrite`.
+
+use crate::{
+    Byte, ByteMarker, Checksum, DecoderState, Error, Frame, MarkerConfig, Result, TailMode,
+    Terminator,
+};
+use bytes::{Buf, Bytes, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Splits incoming bytes at each mark and appends the mark to outgoing frames.
+///
+/// Once the tail is decoded, or decoding is aborted, no further frames are
+/// produced - anything that follows is dropped until the underlying stream ends.
+/// `FramedRead` only ends with the stream, so check `is_finished` to stop reading
+/// from a peer that keeps the connection open.
+///
+/// Frames are split off the read buffer without copying. `FramedRead` ends at the
+/// first error, so `OverflowPolicy::Discard` - and reading on past a frame that
+/// fails its checksum - only help when calling `decode` directly.
+pub struct MarkCodec {
+    marker: ByteMarker,
+    decoder: crate::Decoder,
+}
+
+impl MarkCodec {
+    pub fn with_defaults() -> Self {
+        Self::from_marker(ByteMarker::with_defaults())
+    }
+
+    pub fn new(mark: &str, tail: &str) -> Self {
+        Self::from_marker(ByteMarker::new(mark, tail))
+    }
+
+    /// Takes the mark, tail, limits, overflow policy, strictness and checksum from
+    /// `config`. The tail always ends the stream - `TailMode::BatchEnd` is ignored.
+    pub fn from_config(config: &MarkerConfig) -> Self {
+        let mut codec = Self::from_marker(ByteMarker::from_config(config));
+        codec.decoder = crate::Decoder::from_config(config).tail_mode(TailMode::Stop);
+        codec
+    }
+
+    pub fn from_marker(marker: ByteMarker) -> Self {
+        Self {
+            decoder: crate::Decoder::from_marker(marker.clone()),
+            marker,
+        }
+    }
+
+    /// In strict mode, bytes left without a mark or tail when the stream ends are
+    /// reported as `Error::Truncated` rather than being decoded as a last frame.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.decoder = self.decoder.strict(strict);
+        self
+    }
+
+    /// Frames longer than `len` bytes are reported as `Error::FrameTooLarge`
+    /// instead of being buffered.
+    pub fn max_frame_len(mut self, len: usize) -> Self {
+        self.decoder = self.decoder.max_frame_len(len);
+        self
+    }
+
+    /// Decoding stops with `Error::StreamTooLarge` once the frames and marks decoded
+    /// come to more than `len` bytes.
+    pub fn max_stream_len(mut self, len: usize) -> Self {
+        self.decoder = self.decoder.max_stream_len(len);
+        self
+    }
+
+    /// Expects every frame to carry a `checksum` - see `crate::Decoder::checksum`.
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.decoder = self.decoder.checksum(checksum);
+        self
+    }
+
+    /// Whether the tail has been decoded or decoding was aborted - no more frames
+    /// will follow.
+    pub fn is_finished(&self) -> bool {
+        self.decoder.state() == DecoderState::Done
+    }
+
+    fn next_frame(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>> {
+        let found = self.decoder.frame_in(src);
+        let mut used = src.split_to(self.decoder.release());
+        //Once finished, whatever follows is dropped
+        if self.is_finished() {
+            src.clear();
+        }
+        match found {
+            Some(Ok((range, _))) => {
+                used.truncate(range.end);
+                used.advance(range.start);
+                Ok(Some(used))
+            }
+            Some(Err(err)) => Err(err),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Decoder for MarkCodec {
+    type Item = BytesMut;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>> {
+        self.next_frame(src)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>> {
+        self.decoder.finish();
+        self.next_frame(src)
+    }
+}
+
+impl Encoder<Bytes> for MarkCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: Bytes, dst: &mut BytesMut) -> Result<()> {
+        let mark = self.marker.mark();
+        dst.reserve(item.len() + mark.len());
+        dst.extend_from_slice(&item);
+        dst.extend_from_slice(mark);
+        Ok(())
+    }
+}
+
+/// Writes the frame followed by what its `terminator` says - the mark, the tail,
+/// or nothing for `Terminator::Eof`. The start offset is not written.
+impl<T: AsRef<[Byte]>> Encoder<Frame<T>> for MarkCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: Frame<T>, dst: &mut BytesMut) -> Result<()> {
+        let end = match item.terminator {
+            Terminator::Mark => self.marker.mark(),
+            Terminator::Tail => self.marker.tail(),
+            Terminator::Eof => &[],
+        };
+        let data = item.data.as_ref();
+        dst.reserve(data.len() + end.len());
+        dst.extend_from_slice(data);
+        dst.extend_from_slice(end);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::OverflowPolicy;
+    use futures::{SinkExt, StreamExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    #[tokio::test]
+    async fn test_frames_over_duplex() {
+        let (client, server) = tokio::io::duplex(8);
+        let writer = tokio::spawn(async move {
+            let mut framed = FramedWrite::new(client, MarkCodec::new("sUfFiX", "tAiL"));
+            for msg in ["Streaming", " with", "", " marks"] {
+                framed.send(Bytes::from(msg)).await.unwrap();
+            }
+            let client = framed.get_mut();
+            client
+                .write_all(b" and tailtAiLignoredsUfFiX")
+                .await
+                .unwrap();
+        });
+
+        let mut framed = FramedRead::new(server, MarkCodec::new("sUfFiX", "tAiL"));
+        let mut frames = Vec::new();
+        while let Some(frame) = framed.next().await {
+            frames.push(String::from_utf8(frame.unwrap().to_vec()).unwrap());
+        }
+        writer.await.unwrap();
+        assert_eq!(frames, ["Streaming", " with", "", " marks", " and tail"]);
+        assert!(framed.decoder().is_finished());
+    }
+
+    #[tokio::test]
+    async fn test_unterminated_bytes_at_eof() {
+        let (mut client, server) = tokio::io::duplex(64);
+        client.write_all(b"onesUfFiXtwosUf").await.unwrap();
+        drop(client);
+        let mut framed = FramedRead::new(server, MarkCodec::new("sUfFiX", "tAiL").strict(true));
+        assert_eq!(&framed.next().await.unwrap().unwrap()[..], b"one");
+        assert!(matches!(
+            framed.next().await,
+            Some(Err(Error::Truncated { bytes: 6 }))
+        ));
+
+        let mut decoder = MarkCodec::new("sUfFiX", "tAiL");
+        let mut src = BytesMut::from(&b"twosUf"[..]);
+        assert!(decoder.decode(&mut src).unwrap().is_none());
+        assert_eq!(
+            &decoder.decode_eof(&mut src).unwrap().unwrap()[..],
+            b"twosUf"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tail_written_and_limits_applied() {
+        let (client, mut server) = tokio::io::duplex(64);
+        let mut framed = FramedWrite::new(client, MarkCodec::new("sUfFiX", "tAiL"));
+        for (data, terminator) in [
+            (&b"one"[..], Terminator::Mark),
+            (b"way too long", Terminator::Mark),
+            (b"two", Terminator::Tail),
+        ] {
+            let frame = Frame {
+                data,
+                start_offset: 0,
+                terminator,
+            };
+            framed.send(frame).await.unwrap();
+        }
+        framed.send(Bytes::from("ignored")).await.unwrap();
+        drop(framed);
+
+        let config = MarkerConfig::new("sUfFiX", "tAiL")
+            .unwrap()
+            .max_frame_len(4)
+            .overflow_policy(OverflowPolicy::Discard);
+        let mut codec = MarkCodec::from_config(&config);
+        let mut src = BytesMut::new();
+        server.read_buf(&mut src).await.unwrap();
+        assert_eq!(&src[..], b"onesUfFiXway too longsUfFiXtwotAiLignoredsUfFiX");
+        assert_eq!(&codec.decode(&mut src).unwrap().unwrap()[..], b"one");
+        assert!(matches!(
+            codec.decode(&mut src),
+            Err(Error::FrameTooLarge { limit: 4 })
+        ));
+        assert_eq!(&codec.decode(&mut src).unwrap().unwrap()[..], b"two");
+        //The tail ends decoding - what follows it is dropped
+        assert!(codec.is_finished());
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        assert!(src.is_empty());
+        let mut src = BytesMut::from(&b"moresUfFiX"[..]);
+        assert!(codec.decode_eof(&mut src).unwrap().is_none());
+
+        //Checksums set in the config are checked and stripped
+        let config = MarkerConfig::new("sUfFiX", "tAiL")
+            .unwrap()
+            .checksum(Checksum::Crc32);
+        let mut codec = MarkCodec::from_config(&config);
+        let mut marked = Vec::new();
+        ByteMarker::new("sUfFiX", "tAiL").mark_checked(&mut marked, b"one", Checksum::Crc32);
+        let mut src = BytesMut::from(&marked[..]);
+        assert_eq!(&codec.decode(&mut src).unwrap().unwrap()[..], b"one");
+
+        //FramedRead ends at the first error, even when discarding
+        let config = MarkerConfig::new("sUfFiX", "tAiL")
+            .unwrap()
+            .max_frame_len(4)
+            .overflow_policy(OverflowPolicy::Discard);
+        let (mut client, server) = tokio::io::duplex(64);
+        client
+            .write_all(b"way too longsUfFiXtwosUfFiX")
+            .await
+            .unwrap();
+        drop(client);
+        let mut framed = FramedRead::new(server, MarkCodec::from_config(&config));
+        assert!(matches!(
+            framed.next().await,
+            Some(Err(Error::FrameTooLarge { limit: 4 }))
+        ));
+        assert!(framed.next().await.is_none());
+    }
+}
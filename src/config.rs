@@ -0,0 +1,453 @@
+//! ## MarkerConfig
+//!
+//! Mark, tail and reading settings, built in code or loaded from the environment
+//! or a file, and accepted by every marker and reader of this crate.
+
+#[cfg(any(feature = "std", feature = "serde"))]
+use crate::Error;
+use crate::{spec, Byte, ByteMarks, Checksum, OverflowPolicy, Result, TailMode};
+#[cfg(feature = "serde")]
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::{env, fs, path::Path};
+
+//The built-in specs spell out a `\n` ending the mark and tail - the line break
+//after it is dropped like any other trailing one. Streams written by earlier
+//versions end their frames in `sUfFiX\n`, and stay readable with the newline kept.
+const DEFAULT_MARK: &str = include_str!("byte_mark");
+const DEFAULT_TAIL: &str = include_str!("byte_tail");
+
+/// Validated mark and tail, along with the limits and modes applied by the readers.
+///
+/// Built with `new` and the consuming setters, or loaded with `from_env` or
+/// `from_file`. Readers pick up the settings that apply to them - `Marked`,
+/// `Decoder` and `AsyncMarked` all of them, `MarkCodec` all but the tail mode.
+///
+/// With the `serde` feature, the mark and tail are (de)serialized as specs, and
+/// deserializing fails on the same invalid marks as `new` does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "ConfigSpec", into = "ConfigSpec")
+)]
+pub struct MarkerConfig {
+    mark: Vec<Byte>,
+    tail: Vec<Byte>,
+    pub(crate) max_frame_len: Option<usize>,
+    pub(crate) max_stream_len: Option<usize>,
+    pub(crate) overflow_policy: OverflowPolicy,
+    pub(crate) tail_mode: TailMode,
+    pub(crate) strict: bool,
+    pub(crate) checksum: Option<Checksum>,
+}
+
+impl Default for MarkerConfig {
+    /// The built-in mark and tail, `sUfFiX\n` and `tAiL\n`, without limits. Both
+    /// end in a newline, for compatibility with streams already marked with them.
+    fn default() -> Self {
+        Self::from_specs(DEFAULT_MARK, DEFAULT_TAIL).expect("Built-in mark and tail are valid")
+    }
+}
+
+impl MarkerConfig {
+    /// An empty `tail` means no tail. Fails with `Error::InvalidConfig` if the mark
+    /// is empty or the same as the tail, or if either repeats a byte.
+    pub fn new(mark: impl AsRef<[Byte]>, tail: impl AsRef<[Byte]>) -> Result<Self> {
+        let (mark, tail) = (mark.as_ref(), tail.as_ref());
+        ByteMarks::validate(mark, tail)?;
+        Ok(Self {
+            mark: mark.to_vec(),
+            tail: tail.to_vec(),
+            max_frame_len: None,
+            max_stream_len: None,
+            overflow_policy: OverflowPolicy::default(),
+            tail_mode: TailMode::default(),
+            strict: false,
+            checksum: None,
+        })
+    }
+
+    /// Like `new`, but the mark and tail are written as specs - text with escapes
+    /// such as `\r\n` or `\x00`, `hex:7e7e` or `base64:fn4=`. A trailing line
+    /// ending is dropped rather than made part of the mark.
+    pub fn from_specs(mark: &str, tail: &str) -> Result<Self> {
+        Self::new(spec::parse(mark)?, spec::parse(tail)?)
+    }
+
+    /// Reads the mark and tail specs from the `byte_mark` and `byte_tail` environment
+    /// variables - the built-in ones are used for a variable that is not set.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn from_env() -> Result<Self> {
+        let mark = env_var("byte_mark")?;
+        let tail = env_var("byte_tail")?;
+        Self::from_specs(
+            mark.as_deref().unwrap_or(DEFAULT_MARK),
+            tail.as_deref().unwrap_or(DEFAULT_TAIL),
+        )
+    }
+
+    /// Reads a file of `key = value` lines. Keys are `mark`, `tail`, `max_frame_len`,
+    /// `max_stream_len`, `overflow_policy` (`abort` or `discard`), `tail_mode` (`stop`
+    /// or `batch_end`), `strict` (`true` or `false`) and `checksum` (`none`, `crc16`,
+    /// `crc32` or `crc32c`). Blank lines and lines starting with `#` are skipped, and a
+    /// value may be put in double quotes to keep leading or trailing spaces. The mark
+    /// and tail are specs, as taken by `from_specs`. Keys left out keep their defaults.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let mut mark = DEFAULT_MARK;
+        let mut tail = DEFAULT_TAIL;
+        let mut settings = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| {
+                Error::InvalidConfig(format!("Line {}: expected `key = value`", number + 1))
+            })?;
+            let value = unquote(value.trim());
+            match key.trim() {
+                "mark" => mark = value,
+                "tail" => tail = value,
+                key => settings.push((number + 1, key, value)),
+            }
+        }
+        let mut config = Self::from_specs(mark, tail)?;
+        for (number, key, value) in settings {
+            config
+                .set(key, value)
+                .map_err(|reason| Error::InvalidConfig(format!("Line {}: {}", number, reason)))?;
+        }
+        Ok(config)
+    }
+
+    pub fn mark(&self) -> &[Byte] {
+        &self.mark
+    }
+
+    /// Empty if there is no tail.
+    pub fn tail(&self) -> &[Byte] {
+        &self.tail
+    }
+
+    /// Frames longer than `len` bytes are reported as `Error::FrameTooLarge`.
+    pub fn max_frame_len(mut self, len: usize) -> Self {
+        self.max_frame_len = Some(len);
+        self
+    }
+
+    /// Reading stops with `Error::StreamTooLarge` after more than `len` bytes of
+    /// frames and marks.
+    pub fn max_stream_len(mut self, len: usize) -> Self {
+        self.max_stream_len = Some(len);
+        self
+    }
+
+    /// Sets what happens after a frame exceeds the maximum frame length.
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow_policy = policy;
+        self
+    }
+
+    /// Sets whether the tail ends the input or just the current batch. `MarkCodec`
+    /// always stops at the tail.
+    pub fn tail_mode(mut self, mode: TailMode) -> Self {
+        self.tail_mode = mode;
+        self
+    }
+
+    /// In strict mode, trailing bytes without a mark or tail are reported as
+    /// `Error::Truncated`.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Frames carry a checksum of their payload before the mark or tail - see
+    /// `Marked::checksum`.
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.checksum = Some(checksum);
+        self
+    }
+
+    #[cfg(feature = "std")]
+    fn set(&mut self, key: &str, value: &str) -> core::result::Result<(), String> {
+        let len = || {
+            value
+                .parse::<usize>()
+                .map_err(|err| format!("{} {:?}: {}", key, value, err))
+        };
+        let unknown = || format!("Unknown {} {:?}", key, value);
+        match key {
+            "max_frame_len" => self.max_frame_len = Some(len()?),
+            "max_stream_len" => self.max_stream_len = Some(len()?),
+            "overflow_policy" => {
+                self.overflow_policy = match value {
+                    "abort" => OverflowPolicy::Abort,
+                    "discard" => OverflowPolicy::Discard,
+                    _ => return Err(unknown()),
+                }
+            }
+            "tail_mode" => {
+                self.tail_mode = match value {
+                    "stop" => TailMode::Stop,
+                    "batch_end" => TailMode::BatchEnd,
+                    _ => return Err(unknown()),
+                }
+            }
+            "strict" => {
+                self.strict = value
+                    .parse()
+                    .map_err(|err| format!("{} {:?}: {}", key, value, err))?
+            }
+            "checksum" => {
+                self.checksum = match value {
+                    "none" => None,
+                    "crc16" => Some(Checksum::Crc16),
+                    "crc32" => Some(Checksum::Crc32),
+                    "crc32c" => Some(Checksum::Crc32c),
+                    _ => return Err(unknown()),
+                }
+            }
+            _ => return Err(format!("Unknown key {:?}", key)),
+        }
+        Ok(())
+    }
+}
+
+//Serialized form of `MarkerConfig` - marks are specs, and left out fields default
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigSpec {
+    mark: String,
+    tail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_frame_len: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_stream_len: Option<usize>,
+    overflow_policy: OverflowPolicy,
+    tail_mode: TailMode,
+    strict: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checksum: Option<Checksum>,
+}
+
+#[cfg(feature = "serde")]
+impl Default for ConfigSpec {
+    fn default() -> Self {
+        MarkerConfig::default().into()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<MarkerConfig> for ConfigSpec {
+    fn from(config: MarkerConfig) -> Self {
+        Self {
+            mark: spec::format(&config.mark),
+            tail: spec::format(&config.tail),
+            max_frame_len: config.max_frame_len,
+            max_stream_len: config.max_stream_len,
+            overflow_policy: config.overflow_policy,
+            tail_mode: config.tail_mode,
+            strict: config.strict,
+            checksum: config.checksum,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<ConfigSpec> for MarkerConfig {
+    type Error = Error;
+
+    fn try_from(spec: ConfigSpec) -> Result<Self> {
+        let mut config = Self::from_specs(&spec.mark, &spec.tail)?;
+        config.max_frame_len = spec.max_frame_len;
+        config.max_stream_len = spec.max_stream_len;
+        config.overflow_policy = spec.overflow_policy;
+        config.tail_mode = spec.tail_mode;
+        config.strict = spec.strict;
+        config.checksum = spec.checksum;
+        Ok(config)
+    }
+}
+
+#[cfg(feature = "std")]
+fn env_var(key: &str) -> Result<Option<String>> {
+    match env::var(key) {
+        Ok(value) => Ok(Some(value)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(err) => Err(Error::InvalidConfig(format!("{}: {}", key, err))),
+    }
+}
+
+#[cfg(feature = "std")]
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Error;
+    #[cfg(feature = "std")]
+    use std::io::Write;
+
+    #[cfg(feature = "std")]
+    fn config_file(content: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_invalid_marks_are_errors() {
+        assert!(MarkerConfig::new("sUfFiX", "tAiL").is_ok());
+        assert!(MarkerConfig::new("sUfFiX", "").is_ok());
+        for (mark, tail) in [
+            ("", "tAiL"),
+            ("same", "same"),
+            ("sUffiX", "tAiL"),
+            ("sUfFiX", "tAAL"),
+        ] {
+            assert!(
+                matches!(MarkerConfig::new(mark, tail), Err(Error::InvalidConfig(_))),
+                "{} {}",
+                mark,
+                tail
+            );
+        }
+        //Distinct characters sharing a byte cannot be told apart while matching
+        assert!(MarkerConfig::new("éè", "").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_from_file() {
+        let file = config_file(
+            "# Framing for the upload service\n\
+             mark = \" sUfFiX\"\n\
+             tail = hex:7e3c\n\
+             \n\
+             max_frame_len = 1024\n\
+             tail_mode = batch_end\n\
+             overflow_policy = discard\n\
+             strict = true\n\
+             checksum = crc32c\n",
+        );
+        let config = MarkerConfig::from_file(file.path()).unwrap();
+        assert_eq!(
+            config,
+            MarkerConfig::new(" sUfFiX", "~<")
+                .unwrap()
+                .max_frame_len(1024)
+                .tail_mode(TailMode::BatchEnd)
+                .overflow_policy(OverflowPolicy::Discard)
+                .strict(true)
+                .checksum(Checksum::Crc32c)
+        );
+
+        let config = MarkerConfig::from_file(config_file("max_stream_len = 9").path()).unwrap();
+        assert_eq!(config.mark(), MarkerConfig::default().mark());
+        assert_eq!(config.max_stream_len, Some(9));
+
+        for content in [
+            "mark",
+            "mark = aa",
+            "tail = \\q",
+            "colour = red",
+            "strict = yes",
+            "max_frame_len = -1",
+            "checksum = md5",
+        ] {
+            let result = MarkerConfig::from_file(config_file(content).path());
+            assert!(
+                matches!(result, Err(Error::InvalidConfig(_))),
+                "{}",
+                content
+            );
+        }
+        assert!(matches!(
+            MarkerConfig::from_file("tests/no_such_config"),
+            Err(Error::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_specs() {
+        let config = MarkerConfig::from_specs("\\r\\n", "hex:00ff\n").unwrap();
+        assert_eq!(config.mark(), b"\r\n");
+        assert_eq!(config.tail(), b"\x00\xff");
+        //The built-in specs end in an escaped newline, which is part of the mark,
+        //followed by a line break, which is not
+        let config = MarkerConfig::default();
+        assert_eq!(config.mark(), b"sUfFiX\n");
+        assert_eq!(config.tail(), b"tAiL\n");
+        assert!(MarkerConfig::from_specs("a\\x61", "").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serde() {
+        let config: MarkerConfig = toml::from_str(
+            r#"
+            mark = "\\r\\n"
+            tail = "hex:00ff"
+            max_frame_len = 1024
+            tail_mode = "batch_end"
+            checksum = "crc16"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            MarkerConfig::new("\r\n", b"\x00\xff")
+                .unwrap()
+                .max_frame_len(1024)
+                .tail_mode(TailMode::BatchEnd)
+                .checksum(Checksum::Crc16)
+        );
+        let written = toml::to_string(&config).unwrap();
+        assert!(written.contains(r#"tail = '\0\xff'"#), "{}", written);
+        assert_eq!(toml::from_str::<MarkerConfig>(&written).unwrap(), config);
+
+        let config: MarkerConfig = toml::from_str("strict = true").unwrap();
+        assert_eq!(config, MarkerConfig::default().strict(true));
+
+        for invalid in [
+            r#"mark = "aa""#,
+            r#"tail = "\\q""#,
+            "colour = 1",
+            r#"tail_mode = "go""#,
+        ] {
+            assert!(
+                toml::from_str::<MarkerConfig>(invalid).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_from_env() {
+        env::set_var("byte_mark", "<=>");
+        env::remove_var("byte_tail");
+        let config = MarkerConfig::from_env().unwrap();
+        assert_eq!(config.mark(), b"<=>");
+        assert_eq!(config.tail(), MarkerConfig::default().tail());
+
+        env::set_var("byte_tail", "<=>");
+        assert!(MarkerConfig::from_env().is_err());
+        env::remove_var("byte_mark");
+        env::remove_var("byte_tail");
+    }
+}
@@ -0,0 +1,136 @@
+//! ## ConstMarker
+//!
+//! Marks fixed at compile time - validated and indexed by the compiler, so that
+//! nothing is built at runtime.
+
+use crate::Byte;
+use core::fmt;
+
+//Table entry of a byte that is not in the pattern
+const ABSENT: u8 = 0;
+
+/// A mark and tail checked and indexed at compile time. Built with `marker!`, or
+/// with `ConstMarker::new` in a const context, and turned into a `ByteMarker` with
+/// `ByteMarker::from_const`, which matches straight off the precomputed tables.
+///
+/// ```
+/// use byte_marks::{marker, ConstMarker};
+///
+/// const MARKER: ConstMarker = marker!(b"sUfFiX", b"tAiL");
+///
+/// assert!(MARKER.mark_at(b"onesUfFiX", 3));
+/// assert!(MARKER.tail_at(b"twotAiL", 3));
+/// ```
+///
+/// An empty mark, a mark that is the same as the tail or a repeated byte fail the
+/// build:
+///
+/// ```compile_fail
+/// const MARKER: byte_marks::ConstMarker = byte_marks::marker!(b"sUffiX", b"tAiL");
+/// ```
+pub struct ConstMarker {
+    mark: &'static [Byte],
+    tail: &'static [Byte],
+    //One past the position of each byte in the mark/tail, `ABSENT` if not in it
+    mark_indices: [u8; 256],
+    tail_indices: [u8; 256],
+}
+
+impl ConstMarker {
+    /// An empty `tail` means no tail. Panics - failing the build when evaluated at
+    /// compile time - if the mark is empty or the same as the tail, or if either
+    /// repeats a byte or is longer than 255 bytes.
+    pub const fn new(mark: &'static [Byte], tail: &'static [Byte]) -> Self {
+        assert!(!mark.is_empty(), "Mark should not be empty!");
+        assert!(!same(mark, tail), "Mark and tail same! Should not be");
+        Self {
+            mark,
+            tail,
+            mark_indices: indices(mark, "Mark contains duplicate bytes"),
+            tail_indices: indices(tail, "Tail contains duplicate bytes"),
+        }
+    }
+
+    pub const fn mark(&self) -> &'static [Byte] {
+        self.mark
+    }
+
+    /// Empty if there is no tail.
+    pub const fn tail(&self) -> &'static [Byte] {
+        self.tail
+    }
+
+    /// Whether the mark starts at `bytes[index]`.
+    pub fn mark_at(&self, bytes: &[Byte], index: usize) -> bool {
+        Self::matches(&self.mark_indices, self.mark.len(), bytes, index)
+    }
+
+    /// Whether the tail starts at `bytes[index]` - never, if there is no tail.
+    pub fn tail_at(&self, bytes: &[Byte], index: usize) -> bool {
+        !self.tail.is_empty() && Self::matches(&self.tail_indices, self.tail.len(), bytes, index)
+    }
+
+    //Pattern bytes are unique, so the window matches when every byte in it sits at
+    //the one position the table has for it
+    fn matches(indices: &[u8; 256], len: usize, bytes: &[Byte], index: usize) -> bool {
+        bytes.get(index..index + len).is_some_and(|window| {
+            window
+                .iter()
+                .enumerate()
+                .all(|(position, byte)| indices[*byte as usize] as usize == position + 1)
+        })
+    }
+}
+
+impl fmt::Debug for ConstMarker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConstMarker")
+            .field("mark", &self.mark)
+            .field("tail", &self.tail)
+            .finish()
+    }
+}
+
+const fn same(first: &[Byte], second: &[Byte]) -> bool {
+    if first.len() != second.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < first.len() {
+        if first[i] != second[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+const fn indices(pattern: &[Byte], duplicate: &'static str) -> [u8; 256] {
+    assert!(
+        pattern.len() < 256,
+        "Mark and tail must be shorter than 256 bytes"
+    );
+    let mut indices = [ABSENT; 256];
+    let mut i = 0;
+    while i < pattern.len() {
+        let byte = pattern[i] as usize;
+        if indices[byte] != ABSENT {
+            panic!("{}", duplicate);
+        }
+        indices[byte] = i as u8 + 1;
+        i += 1;
+    }
+    indices
+}
+
+/// Builds a `ConstMarker` at compile time from a mark and an optional tail, given
+/// as byte string literals or other constants. Invalid marks fail the build.
+#[macro_export]
+macro_rules! marker {
+    ($mark:expr) => {
+        $crate::marker!($mark, b"")
+    };
+    ($mark:expr, $tail:expr) => {
+        const { $crate::ConstMarker::new($mark, $tail) }
+    };
+}
@@ -0,0 +1,478 @@
+//! ## Decoder
+//!
+//! A sans-IO counterpart of `Marked` - bytes are pushed in as they arrive from
+//! wherever they come from, and complete frames are popped out. `AsyncMarked` and
+//! `MarkCodec` are built on it.
+
+use crate::{
+    Boundary, Byte, ByteMarker, Checksum, Error, Frame, MarkerConfig, OverflowPolicy, Result,
+    TailMode, Terminator,
+};
+use alloc::vec::Vec;
+use core::ops::Range;
+
+/// Where a `Decoder` is at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecoderState {
+    /// Looking for the mark that ends the current frame.
+    Framing,
+    /// Dropping the bytes of an oversized frame up to the next mark.
+    Discarding,
+    /// The tail was seen, the input was finished or reading was aborted - no
+    /// more frames will be produced.
+    Done,
+}
+
+/// Push based frame decoder that does no I/O of its own.
+///
+/// Bytes handed to `push` are buffered until a mark completes a frame. A mark
+/// split across pushes is picked up once the rest of it arrives - the decoder
+/// only ever rescans the few trailing bytes that could be the start of one.
+pub struct Decoder {
+    marker: ByteMarker,
+    buffer: Vec<Byte>,
+    //Start of the current frame within `buffer`
+    start: usize,
+    //No mark or tail starts before this position
+    scanned: usize,
+    //Stream offset of `buffer[0]`
+    offset: usize,
+    state: DecoderState,
+    finished: bool,
+    tail_mode: TailMode,
+    strict: bool,
+    max_frame_len: Option<usize>,
+    max_stream_len: Option<usize>,
+    overflow_policy: OverflowPolicy,
+    checksum: Option<Checksum>,
+    frames_read: usize,
+}
+
+impl Decoder {
+    pub fn with_defaults() -> Self {
+        Self::from_marker(ByteMarker::with_defaults())
+    }
+
+    pub fn new(mark: &str, tail: &str) -> Self {
+        Self::from_marker(ByteMarker::new(mark, tail))
+    }
+
+    /// Takes the mark, tail, limits, overflow policy, tail mode, strictness and
+    /// checksum from `config`.
+    pub fn from_config(config: &MarkerConfig) -> Self {
+        let mut decoder = Self::from_marker(ByteMarker::from_config(config));
+        decoder.max_frame_len = config.max_frame_len;
+        decoder.max_stream_len = config.max_stream_len;
+        decoder.overflow_policy = config.overflow_policy;
+        decoder.tail_mode = config.tail_mode;
+        decoder.strict = config.strict;
+        decoder.checksum = config.checksum;
+        decoder
+    }
+
+    pub fn from_marker(marker: ByteMarker) -> Self {
+        Self {
+            marker,
+            buffer: Vec::new(),
+            start: 0,
+            scanned: 0,
+            offset: 0,
+            state: DecoderState::Framing,
+            finished: false,
+            tail_mode: TailMode::default(),
+            strict: false,
+            max_frame_len: None,
+            max_stream_len: None,
+            overflow_policy: OverflowPolicy::default(),
+            checksum: None,
+            frames_read: 0,
+        }
+    }
+
+    /// Sets whether the tail ends the input or just the current batch.
+    pub fn tail_mode(mut self, mode: TailMode) -> Self {
+        self.tail_mode = mode;
+        self
+    }
+
+    /// In strict mode, bytes left without a mark or tail when the input is
+    /// finished are reported as `Error::Truncated` rather than as a frame.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Frames longer than `len` bytes are reported as `Error::FrameTooLarge`
+    /// instead of being buffered.
+    pub fn max_frame_len(mut self, len: usize) -> Self {
+        self.max_frame_len = Some(len);
+        self
+    }
+
+    /// Decoding stops with `Error::StreamTooLarge` once the frames and marks popped
+    /// come to more than `len` bytes. Bytes pushed but not yet part of a frame do
+    /// not count.
+    pub fn max_stream_len(mut self, len: usize) -> Self {
+        self.max_stream_len = Some(len);
+        self
+    }
+
+    /// Sets what happens after a frame exceeds the maximum frame length.
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow_policy = policy;
+        self
+    }
+
+    /// Expects every frame to carry a `checksum`, as written by
+    /// `ByteMarker::mark_checked`. The checksum is stripped from frames that match
+    /// it, and those that do not are reported as `Error::ChecksumMismatch` - the
+    /// last frame of finished input included.
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.checksum = Some(checksum);
+        self
+    }
+
+    pub fn state(&self) -> DecoderState {
+        self.state
+    }
+
+    /// Number of bytes pushed but not yet handed out as part of a frame.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len() - self.start
+    }
+
+    /// Feeds more input. Bytes pushed after the input was finished, or once the
+    /// decoder is done, are ignored.
+    pub fn push(&mut self, bytes: &[Byte]) {
+        if self.finished || self.state == DecoderState::Done {
+            return;
+        }
+        let used = self.release();
+        self.buffer.drain(..used);
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Marks the end of the input - trailing bytes not followed by a mark are
+    /// handed out by `next_frame` as a last frame ended by `Terminator::Eof`.
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    /// Pops the next complete frame, if the bytes pushed so far hold one.
+    pub fn next_frame(&mut self) -> Option<Result<Frame>> {
+        let buffer = core::mem::take(&mut self.buffer);
+        let frame = self.frame_in(&buffer).map(|found| {
+            found.map(|(range, terminator)| Frame {
+                data: buffer[range.clone()].to_vec(),
+                start_offset: self.offset + range.start,
+                terminator,
+            })
+        });
+        self.buffer = buffer;
+        frame
+    }
+
+    //Finds the next frame in `buf`, which holds the bytes from the last `release`
+    //on, without copying it - for callers that keep the input in their own buffer
+    pub(crate) fn frame_in(&mut self, buf: &[Byte]) -> Option<Result<(Range<usize>, Terminator)>> {
+        loop {
+            if self.state == DecoderState::Done {
+                return None;
+            }
+            let (index, boundary) = match self.marker.find_boundary(buf, self.scanned) {
+                Some(found) => found,
+                None => return self.no_boundary(buf),
+            };
+            let frame_start = self.start;
+            self.start = index + self.marker.boundary_len(boundary);
+            self.scanned = self.start;
+            if let Some(err) = self.stream_overflow(self.start) {
+                return Some(Err(err));
+            }
+
+            let discarded = self.state == DecoderState::Discarding;
+            self.state = match boundary {
+                Boundary::Tail if self.tail_mode == TailMode::Stop => DecoderState::Done,
+                _ => DecoderState::Framing,
+            };
+            if discarded {
+                continue;
+            }
+            if self.exceeds_frame_len(index - frame_start) {
+                return Some(Err(self.frame_overflow()));
+            }
+            return Some(self.checked(buf, frame_start..index, boundary.into()));
+        }
+    }
+
+    //Number of bytes at the front of the input that are done with - handed out,
+    //discarded or part of a mark - and can be dropped
+    pub(crate) fn release(&mut self) -> usize {
+        let used = self.start;
+        self.offset += used;
+        self.scanned -= used;
+        self.start = 0;
+        used
+    }
+
+    fn no_boundary(&mut self, buf: &[Byte]) -> Option<Result<(Range<usize>, Terminator)>> {
+        let partial_start = buf.len().saturating_sub(self.marker.partial_len());
+        self.scanned = partial_start.max(self.start);
+        if self.finished {
+            let discarded = self.state == DecoderState::Discarding;
+            self.state = DecoderState::Done;
+            let frame_start = self.start;
+            let bytes = buf.len() - frame_start;
+            self.start = buf.len();
+            self.scanned = self.start;
+            if bytes == 0 || discarded {
+                return None;
+            }
+            if self.strict {
+                return Some(Err(Error::Truncated { bytes }));
+            }
+            if let Some(err) = self.stream_overflow(buf.len()) {
+                return Some(Err(err));
+            }
+            if self.exceeds_frame_len(bytes) {
+                return Some(Err(self.frame_overflow()));
+            }
+            return Some(self.checked(buf, frame_start..buf.len(), Terminator::Eof));
+        }
+        //A partial mark counts too - it is part of the stream whether or not it completes
+        if let Some(err) = self.stream_overflow(buf.len()) {
+            return Some(Err(err));
+        }
+        match self.state {
+            DecoderState::Discarding => self.start = self.scanned,
+            _ if self.left_over_overflows(buf) => {
+                let err = self.frame_overflow();
+                if self.state == DecoderState::Framing {
+                    self.state = DecoderState::Discarding;
+                    self.start = self.scanned;
+                }
+                return Some(Err(err));
+            }
+            _ => {}
+        }
+        None
+    }
+
+    //Checks and strips the checksum of `buf[range]`, if there is one
+    fn checked(
+        &mut self,
+        buf: &[Byte],
+        range: Range<usize>,
+        terminator: Terminator,
+    ) -> Result<(Range<usize>, Terminator)> {
+        let frame_index = self.frames_read;
+        self.frames_read += 1;
+        let payload_len = match self.checksum {
+            Some(checksum) => checksum
+                .verify(&buf[range.clone()])
+                .ok_or(Error::ChecksumMismatch {
+                    frame_index,
+                    offset: self.offset + range.start,
+                })?
+                .len(),
+            None => range.len(),
+        };
+        Ok((range.start..range.start + payload_len, terminator))
+    }
+
+    //`end` is where the bytes used up so far end within `buffer`
+    fn stream_overflow(&mut self, end: usize) -> Option<Error> {
+        let limit = self
+            .max_stream_len
+            .filter(|limit| self.offset + end > *limit)?;
+        self.state = DecoderState::Done;
+        Some(Error::StreamTooLarge { limit })
+    }
+
+    fn exceeds_frame_len(&self, len: usize) -> bool {
+        self.max_frame_len.is_some_and(|limit| len > limit)
+    }
+
+    fn left_over_overflows(&self, buf: &[Byte]) -> bool {
+        self.max_frame_len.is_some_and(|limit| {
+            buf.len() - self.start > limit.saturating_add(self.marker.partial_len())
+        })
+    }
+
+    fn frame_overflow(&mut self) -> Error {
+        if self.overflow_policy == OverflowPolicy::Abort {
+            self.state = DecoderState::Done;
+        }
+        Error::FrameTooLarge {
+            limit: self.max_frame_len.unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn drain(decoder: &mut Decoder) -> Vec<Result<Frame>> {
+        std::iter::from_fn(|| decoder.next_frame()).collect()
+    }
+
+    fn frame(data: &str, start_offset: usize, terminator: Terminator) -> Frame {
+        Frame {
+            data: data.as_bytes().to_vec(),
+            start_offset,
+            terminator,
+        }
+    }
+
+    #[test]
+    fn test_byte_at_a_time() {
+        let message = "onesUfFiXtwosUfFiXsUfFiXthreetAiLignored";
+        let mut decoder = Decoder::new("sUfFiX", "tAiL");
+        let mut frames = Vec::new();
+        for byte in message.as_bytes() {
+            decoder.push(&[*byte]);
+            frames.extend(drain(&mut decoder).into_iter().map(Result::unwrap));
+            assert!(decoder.buffered() <= "three".len() + 3);
+        }
+        decoder.finish();
+        assert!(decoder.next_frame().is_none());
+        assert_eq!(decoder.state(), DecoderState::Done);
+        assert_eq!(
+            frames,
+            vec![
+                frame("one", 0, Terminator::Mark),
+                frame("two", 9, Terminator::Mark),
+                frame("", 18, Terminator::Mark),
+                frame("three", 24, Terminator::Tail),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_partial_mark_across_pushes() {
+        let mut decoder = Decoder::new("sUfFiX", "tAiL");
+        decoder.push(b"onesUf");
+        assert!(decoder.next_frame().is_none());
+        decoder.push(b"Fi");
+        assert!(decoder.next_frame().is_none());
+        decoder.push(b"Xtw");
+        assert_eq!(
+            decoder.next_frame().unwrap().unwrap(),
+            frame("one", 0, Terminator::Mark)
+        );
+        assert_eq!(decoder.buffered(), 2);
+        decoder.finish();
+        assert_eq!(
+            decoder.next_frame().unwrap().unwrap(),
+            frame("tw", 9, Terminator::Eof)
+        );
+        assert!(decoder.next_frame().is_none());
+    }
+
+    #[test]
+    fn test_batches_and_strict() {
+        let mut decoder = Decoder::new("sUfFiX", "tAiL")
+            .tail_mode(TailMode::BatchEnd)
+            .strict(true);
+        decoder.push(b"onetAiLtwosUfFiXthr");
+        decoder.finish();
+        let frames = drain(&mut decoder);
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].as_ref().unwrap().terminator, Terminator::Tail);
+        assert_eq!(frames[1].as_ref().unwrap().data, b"two");
+        assert!(matches!(frames[2], Err(Error::Truncated { bytes: 3 })));
+    }
+
+    #[test]
+    fn test_oversized_frames() {
+        let mut decoder = Decoder::new("sUfFiX", "")
+            .max_frame_len(4)
+            .overflow_policy(OverflowPolicy::Discard);
+        for chunk in ["onesUfFiXway too", " longsUfF", "iXtwosUfFiX"] {
+            decoder.push(chunk.as_bytes());
+        }
+        let frames = drain(&mut decoder);
+        assert!(matches!(frames[1], Err(Error::FrameTooLarge { limit: 4 })));
+        assert_eq!(frames[2].as_ref().unwrap().data, b"two");
+
+        let mut decoder = Decoder::new("sUfFiX", "").max_frame_len(4);
+        decoder.push(b"way too long");
+        assert!(matches!(
+            decoder.next_frame(),
+            Some(Err(Error::FrameTooLarge { limit: 4 }))
+        ));
+        assert_eq!(decoder.state(), DecoderState::Done);
+
+        let mut decoder = Decoder::new("sUfFiX", "")
+            .max_frame_len(4)
+            .overflow_policy(OverflowPolicy::Discard);
+        for _ in 0..1000 {
+            decoder.push(b"endless");
+            let _ = drain(&mut decoder);
+            assert!(decoder.buffered() < 4 + "endless".len());
+        }
+        decoder.push(b"sUfFiXok");
+        decoder.finish();
+        let frames = drain(&mut decoder);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].as_ref().unwrap().data, b"ok");
+    }
+
+    #[test]
+    fn test_stream_too_large() {
+        let mut decoder = Decoder::new("sUfFiX", "").max_stream_len(18);
+        decoder.push(b"onesUfFiXtwosUfFiXthreesUfFiX");
+        let frames = drain(&mut decoder);
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[1].as_ref().unwrap().data, b"two");
+        assert!(matches!(
+            frames[2],
+            Err(Error::StreamTooLarge { limit: 18 })
+        ));
+        assert_eq!(decoder.state(), DecoderState::Done);
+
+        //Nor does a frame that never ends get past the limit
+        let mut decoder = Decoder::new("sUfFiX", "").max_stream_len(18);
+        for _ in 0..4 {
+            decoder.push(b"endless");
+        }
+        assert!(matches!(
+            decoder.next_frame(),
+            Some(Err(Error::StreamTooLarge { limit: 18 }))
+        ));
+    }
+
+    #[test]
+    fn test_checksums() {
+        let marker = ByteMarker::new("sUfFiX", "tAiL");
+        let mut bytes = Vec::new();
+        marker.mark_checked(&mut bytes, b"one", Checksum::Crc32);
+        marker.mark_checked(&mut bytes, b"two", Checksum::Crc32);
+        bytes[17] ^= 0x20;
+        bytes.extend(b"three");
+        let config = MarkerConfig::new("sUfFiX", "tAiL")
+            .unwrap()
+            .checksum(Checksum::Crc32);
+        let mut decoder = Decoder::from_config(&config);
+        decoder.push(&bytes);
+        decoder.finish();
+        let frames = drain(&mut decoder);
+        assert_eq!(frames[0].as_ref().unwrap().data, b"one");
+        assert!(matches!(
+            frames[1],
+            Err(Error::ChecksumMismatch {
+                frame_index: 1,
+                offset: 17
+            })
+        ));
+        //Unterminated bytes are checked too
+        assert!(matches!(
+            frames[2],
+            Err(Error::ChecksumMismatch {
+                frame_index: 2,
+                offset: 34
+            })
+        ));
+    }
+}
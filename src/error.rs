@@ -0,0 +1,144 @@
+//! ## Error
+//!
+
+#[cfg(feature = "hmac")]
+use crate::AuthFailure;
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io;
+
+/// Errors surfaced while configuring markers or recovering frames from marked bytes.
+///
+/// Variants may be added in later releases, so matches need a wildcard arm.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// A frame grew past the configured maximum frame length.
+    FrameTooLarge { limit: usize },
+    /// More bytes than the configured maximum were read from the stream.
+    StreamTooLarge { limit: usize },
+    /// The stream ended with `bytes` bytes that were not followed by a mark or tail.
+    Truncated { bytes: usize },
+    /// The frame at `frame_index`, starting `offset` bytes in, does not match its checksum.
+    ChecksumMismatch { frame_index: usize, offset: usize },
+    /// The frame at `frame_index`, starting `offset` bytes in, failed authentication.
+    #[cfg(feature = "hmac")]
+    Unauthenticated {
+        frame_index: usize,
+        offset: usize,
+        reason: AuthFailure,
+    },
+    /// The frame at `frame_index`, starting `offset` bytes in, could not be decrypted.
+    #[cfg(feature = "aead")]
+    Undecryptable { frame_index: usize, offset: usize },
+    /// A mark, tail or setting could not be used - the reason says which and why.
+    #[cfg(feature = "alloc")]
+    InvalidConfig(String),
+    /// The underlying reader failed.
+    #[cfg(feature = "std")]
+    Io(io::Error),
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::FrameTooLarge { limit } => {
+                write!(f, "Frame exceeds the maximum length of {} bytes", limit)
+            }
+            Error::StreamTooLarge { limit } => {
+                write!(f, "Stream exceeds the maximum length of {} bytes", limit)
+            }
+            Error::Truncated { bytes } => {
+                write!(f, "Stream ended with {} unterminated bytes", bytes)
+            }
+            Error::ChecksumMismatch {
+                frame_index,
+                offset,
+            } => write!(
+                f,
+                "Checksum mismatch in frame {} at offset {}",
+                frame_index, offset
+            ),
+            #[cfg(feature = "hmac")]
+            Error::Unauthenticated {
+                frame_index,
+                offset,
+                reason,
+            } => write!(
+                f,
+                "Frame {} at offset {} failed authentication: {}",
+                frame_index, offset, reason
+            ),
+            #[cfg(feature = "aead")]
+            Error::Undecryptable {
+                frame_index,
+                offset,
+            } => write!(
+                f,
+                "Frame {} at offset {} could not be decrypted",
+                frame_index, offset
+            ),
+            #[cfg(feature = "alloc")]
+            Error::InvalidConfig(reason) => write!(f, "Invalid marker configuration: {}", reason),
+            #[cfg(feature = "std")]
+            Error::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "std")]
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(err) => err,
+            other => io::Error::new(io::ErrorKind::InvalidData, other),
+        }
+    }
+}
+
+/// The bytes did not end with the expected mark or tail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotMarked;
+
+impl fmt::Display for NotMarked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bytes do not end with the expected marking")
+    }
+}
+
+impl core::error::Error for NotMarked {}
+
+/// A frame did not fit in a fixed size buffer of `capacity` bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overflow {
+    pub capacity: usize,
+}
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Frame does not fit in {} bytes", self.capacity)
+    }
+}
+
+impl core::error::Error for Overflow {}
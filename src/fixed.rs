@@ -0,0 +1,222 @@
+//! ## Fixed capacity framing
+//!
+//! Framing for targets without an allocator - the mark and the frame buffer are
+//! sized by const generics, and nothing is ever allocated.
+
+use crate::{Byte, Overflow};
+
+/// Byte at a time frame decoder with a fixed `BUF` byte frame buffer, for a mark
+/// of `MARK` bytes. There is no tail - every frame is ended by the mark.
+///
+/// Bytes are fed one by one, as they come off a UART or the like, and each
+/// complete frame is handed back as a slice of the buffer. The slice is only good
+/// until the next `push`, which starts on the next frame.
+pub struct StaticDecoder<const MARK: usize, const BUF: usize> {
+    mark: [Byte; MARK],
+    buffer: [Byte; BUF],
+    len: usize,
+    //Number of bytes seen so far of a mark that may be starting
+    matched: usize,
+    discarding: bool,
+    frame_returned: bool,
+}
+
+impl<const MARK: usize, const BUF: usize> StaticDecoder<MARK, BUF> {
+    /// Panics if the mark is empty or repeats a byte - in a const context, that
+    /// fails the build instead.
+    pub const fn new(mark: [Byte; MARK]) -> Self {
+        check_mark(&mark);
+        Self {
+            mark,
+            buffer: [0; BUF],
+            len: 0,
+            matched: 0,
+            discarding: false,
+            frame_returned: false,
+        }
+    }
+
+    /// Feeds the next byte, returning the frame it completes, if any.
+    ///
+    /// A frame longer than `BUF` bytes is reported once as `Overflow`, and its
+    /// remaining bytes up to the next mark are dropped.
+    pub fn push(&mut self, byte: Byte) -> Result<Option<&[Byte]>, Overflow> {
+        if self.frame_returned {
+            self.len = 0;
+            self.frame_returned = false;
+        }
+        if byte == self.mark[self.matched] {
+            self.matched += 1;
+            if self.matched < MARK {
+                return Ok(None);
+            }
+            self.matched = 0;
+            if self.discarding {
+                self.discarding = false;
+                return Ok(None);
+            }
+            self.frame_returned = true;
+            return Ok(Some(&self.buffer[..self.len]));
+        }
+        //What looked like the start of a mark was payload after all. Mark bytes do
+        //not repeat, so only `byte` itself can start another one.
+        let pending = self.matched;
+        self.matched = usize::from(byte == self.mark[0]);
+        for i in 0..pending {
+            self.append(self.mark[i])?;
+        }
+        if self.matched == 0 {
+            self.append(byte)?;
+        }
+        Ok(None)
+    }
+
+    /// Drops the frame in progress, say after the line was reset.
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.matched = 0;
+        self.discarding = false;
+        self.frame_returned = false;
+    }
+
+    fn append(&mut self, byte: Byte) -> Result<(), Overflow> {
+        if self.discarding {
+            return Ok(());
+        }
+        if self.len == BUF {
+            self.len = 0;
+            self.discarding = true;
+            return Err(Overflow { capacity: BUF });
+        }
+        self.buffer[self.len] = byte;
+        self.len += 1;
+        Ok(())
+    }
+}
+
+/// Writes payloads followed by a `MARK` byte mark into caller provided buffers -
+/// the counterpart of `StaticDecoder`.
+pub struct StaticEncoder<const MARK: usize> {
+    mark: [Byte; MARK],
+}
+
+impl<const MARK: usize> StaticEncoder<MARK> {
+    /// Panics if the mark is empty or repeats a byte - in a const context, that
+    /// fails the build instead.
+    pub const fn new(mark: [Byte; MARK]) -> Self {
+        check_mark(&mark);
+        Self { mark }
+    }
+
+    /// Writes `payload` and the mark to the start of `out`, returning the number of
+    /// bytes written. `out` is left untouched if they do not fit.
+    pub fn encode(&self, payload: &[Byte], out: &mut [Byte]) -> Result<usize, Overflow> {
+        let len = payload.len() + MARK;
+        if len > out.len() {
+            return Err(Overflow {
+                capacity: out.len(),
+            });
+        }
+        out[..payload.len()].copy_from_slice(payload);
+        out[payload.len()..len].copy_from_slice(&self.mark);
+        Ok(len)
+    }
+}
+
+const fn check_mark(mark: &[Byte]) {
+    assert!(!mark.is_empty(), "Mark should not be empty!");
+    let mut i = 0;
+    while i < mark.len() {
+        let mut j = i + 1;
+        while j < mark.len() {
+            assert!(mark[i] != mark[j], "Mark contains duplicate bytes");
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    //Runs `bytes` through the decoder, collecting frames and overflows as a UART
+    //interrupt handler would
+    fn feed<const MARK: usize, const BUF: usize>(
+        decoder: &mut StaticDecoder<MARK, BUF>,
+        bytes: &[Byte],
+    ) -> Vec<Result<Vec<Byte>, Overflow>> {
+        let mut frames = Vec::new();
+        for byte in bytes {
+            match decoder.push(*byte) {
+                Ok(Some(frame)) => frames.push(Ok(frame.to_vec())),
+                Ok(None) => {}
+                Err(overflow) => frames.push(Err(overflow)),
+            }
+        }
+        frames
+    }
+
+    #[test]
+    fn test_frames_and_partial_marks() {
+        let mut decoder = StaticDecoder::<6, 16>::new(*b"sUfFiX");
+        let frames = feed(&mut decoder, b"onesUfFiXsUfsUfFiXtwo sUfFisUfFiX");
+        assert_eq!(
+            frames,
+            vec![
+                Ok(b"one".to_vec()),
+                Ok(b"sUf".to_vec()),
+                Ok(b"two sUfFi".to_vec())
+            ]
+        );
+        assert_eq!(feed(&mut decoder, b"sUfFiX"), vec![Ok(Vec::new())]);
+    }
+
+    #[test]
+    fn test_overflow_drops_rest_of_frame() {
+        let mut decoder = StaticDecoder::<2, 4>::new(*b"\r\n");
+        let frames = feed(&mut decoder, b"1234\r\n12345678\r\nok\r\n");
+        assert_eq!(
+            frames,
+            vec![
+                Ok(b"1234".to_vec()),
+                Err(Overflow { capacity: 4 }),
+                Ok(b"ok".to_vec())
+            ]
+        );
+        //A mark split by the overflow still ends the dropped frame
+        assert_eq!(
+            feed(&mut decoder, b"1234\r\r\nok\r\n"),
+            vec![Err(Overflow { capacity: 4 }), Ok(b"ok".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_encoder_round_trip() {
+        const ENCODER: StaticEncoder<2> = StaticEncoder::new(*b"\r\n");
+        let mut decoder = StaticDecoder::<2, 8>::new(*b"\r\n");
+        let mut out = [0; 32];
+        let mut written = 0;
+        for payload in [&b"one"[..], b"", b"three"] {
+            written += ENCODER.encode(payload, &mut out[written..]).unwrap();
+        }
+        assert_eq!(&out[..written], b"one\r\n\r\nthree\r\n");
+        assert_eq!(
+            feed(&mut decoder, &out[..written]),
+            vec![Ok(b"one".to_vec()), Ok(Vec::new()), Ok(b"three".to_vec())]
+        );
+
+        let mut out = [0; 4];
+        assert_eq!(
+            ENCODER.encode(b"four", &mut out),
+            Err(Overflow { capacity: 4 })
+        );
+        assert_eq!(out, [0; 4]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_repeated_mark_byte() {
+        StaticDecoder::<3, 8>::new(*b"aba");
+    }
+}
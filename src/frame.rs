@@ -0,0 +1,43 @@
+//! ## Frame
+//!
+
+use crate::{Boundary, Byte};
+use alloc::vec::Vec;
+
+/// What brought a frame to an end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Terminator {
+    /// The frame was followed by the mark.
+    Mark,
+    /// The frame was followed by the tail.
+    Tail,
+    /// The bytes ran out before a mark or tail was seen.
+    Eof,
+}
+
+impl From<Boundary> for Terminator {
+    fn from(boundary: Boundary) -> Self {
+        match boundary {
+            Boundary::Mark => Terminator::Mark,
+            Boundary::Tail => Terminator::Tail,
+        }
+    }
+}
+
+/// A frame of unmarked bytes along with where it was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame<T = Vec<Byte>> {
+    /// The frame bytes, with the mark or tail stripped off.
+    pub data: T,
+    /// Offset of the first frame byte from the start of the stream.
+    pub start_offset: usize,
+    /// What ended the frame.
+    pub terminator: Terminator,
+}
+
+impl<T: AsRef<[Byte]>> Frame<T> {
+    /// Offset just past the last frame byte.
+    pub fn end_offset(&self) -> usize {
+        self.start_offset + self.data.as_ref().len()
+    }
+}
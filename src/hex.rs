@@ -0,0 +1,44 @@
+//! ## Hex
+//!
+//! Lowercase hex digits, the encoding of everything the crate adds to a frame -
+//! checksums, tags and ciphertext - so that none of it can hold bytes outside
+//! `0-9a-f`.
+
+use crate::Byte;
+#[cfg(any(feature = "hmac", feature = "aead"))]
+use alloc::vec::Vec;
+
+pub(crate) const DIGITS: &[Byte; 16] = b"0123456789abcdef";
+
+/// Appends two digits per byte of `bytes` to `out`.
+#[cfg(any(feature = "hmac", feature = "aead"))]
+pub(crate) fn push(bytes: &[Byte], out: &mut Vec<Byte>) {
+    out.reserve(2 * bytes.len());
+    for byte in bytes {
+        out.push(DIGITS[(byte >> 4) as usize]);
+        out.push(DIGITS[(byte & 0xf) as usize]);
+    }
+}
+
+/// Fills `out` with the bytes `digits` encode. Fails unless there are exactly two
+/// digits per byte of `out`, or on anything `push` does not write, uppercase digits
+/// included.
+#[cfg(any(feature = "hmac", feature = "aead"))]
+pub(crate) fn decode(digits: &[Byte], out: &mut [Byte]) -> Option<()> {
+    if digits.len() != 2 * out.len() {
+        return None;
+    }
+    for (byte, pair) in out.iter_mut().zip(digits.chunks(2)) {
+        *byte = value(pair[0])? << 4 | value(pair[1])?;
+    }
+    Some(())
+}
+
+#[cfg(any(feature = "hmac", feature = "aead"))]
+fn value(digit: Byte) -> Option<Byte> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        _ => None,
+    }
+}
@@ -1,3 +1,4 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 #![cfg_attr(docsrs, feature(doc_cfg))]
 #![deny(elided_lifetimes_in_paths)]
 #![deny(rust_2018_idioms)]
@@ -7,29 +8,111 @@
 //! transmitting and receiving bytes from network/files. This comes very handy while building
 //! application network protocols - one could read off the demarcated bytes of the wire and
 //! could use bincode <https://github.com/bincode-org/bincode> to reconstruct a struct from those bytes. The demarcating byte pattern
-//! is configured via files called `byte_mark/byte_tail` or environment variables named
-//! similarly and or in code. The characters in the pattern should not repeat.
+//! is configured in code via `MarkerConfig`, which can also be loaded from a file or from the
+//! environment variables `byte_mark/byte_tail`. The bytes in the pattern should not repeat.
+//!
+//! Marking, unmarking and the `Decoder` only need the `alloc` feature. The default `std`
+//! feature adds `Marked`, the file and environment loaders and the I/O adapters. With
+//! neither, `StaticDecoder` and `StaticEncoder` frame bytes in fixed size buffers.
+//! Frames can carry a CRC to catch corruption, and with the `hmac` feature an HMAC tag to
+//! catch tampering - see `Checksum`, `Signer` and `Verifier`. The `aead` feature encrypts
+//! them with `Encryptor` and `Decryptor`.
 //!
 
-use lazy_static::lazy_static;
-use std::env;
-lazy_static! {
-    pub static ref MARK: &'static str = Box::leak({
-        let markings =
-            env::var("byte_mark").unwrap_or_else(|_| include_str!("byte_mark").to_string());
-        markings.into_boxed_str()
-    });
-    pub static ref TAIL: &'static str = Box::leak({
-        let tail = env::var("byte_tail").unwrap_or_else(|_| include_str!("byte_tail").to_string());
-        tail.into_boxed_str()
-    });
-}
+#[cfg(feature = "alloc")]
+extern crate alloc;
 
+#[cfg(feature = "hmac")]
+#[cfg_attr(docsrs, doc(cfg(feature = "hmac")))]
+pub use auth::{AuthFailure, Signer, Verifier, AUTH_LEN};
 pub(crate) type Byte = u8;
+#[cfg(feature = "alloc")]
+pub(crate) use bytemarker::Boundary;
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
 pub use bytemarker::ByteMarker;
+#[cfg(feature = "aead")]
+#[cfg_attr(docsrs, doc(cfg(feature = "aead")))]
+pub use bytemarker::Decrypted;
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub use bytemarker::{Batches, Unmarked};
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
 pub use bytemarks::ByteMarks;
-pub use marked::Marked;
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub use checksum::Checksum;
+#[cfg(feature = "aead")]
+#[cfg_attr(docsrs, doc(cfg(feature = "aead")))]
+pub use cipher::{Cipher, DecryptionFailed, Decryptor, Encryptor, SEAL_LEN};
+#[cfg(feature = "tokio")]
+#[cfg_attr(docsrs, doc(cfg(feature = "tokio")))]
+pub use codec::MarkCodec;
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub use config::MarkerConfig;
+pub use constmarker::ConstMarker;
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub use decoder::{Decoder, DecoderState};
+pub use error::{Error, NotMarked, Overflow, Result};
+pub use fixed::{StaticDecoder, StaticEncoder};
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub use frame::{Frame, Terminator};
+#[cfg(feature = "mmap")]
+#[cfg_attr(docsrs, doc(cfg(feature = "mmap")))]
+pub use mapped::{partition, MappedMarked};
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub use marked::{Event, Marked};
+pub use policy::{OverflowPolicy, TailMode};
+#[cfg(feature = "futures")]
+#[cfg_attr(docsrs, doc(cfg(feature = "futures")))]
+pub use sink::MarkedSink;
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub use split::{Segment, Split};
+#[cfg(feature = "futures")]
+#[cfg_attr(docsrs, doc(cfg(feature = "futures")))]
+pub use stream::AsyncMarked;
 
+#[cfg(feature = "hmac")]
+mod auth;
+#[cfg(feature = "alloc")]
 mod bytemarker;
+#[cfg(feature = "alloc")]
 mod bytemarks;
+#[cfg(feature = "alloc")]
+mod checksum;
+#[cfg(feature = "aead")]
+mod cipher;
+#[cfg(feature = "tokio")]
+mod codec;
+#[cfg(feature = "alloc")]
+mod config;
+mod constmarker;
+#[cfg(feature = "alloc")]
+mod decoder;
+mod error;
+mod fixed;
+#[cfg(feature = "alloc")]
+mod frame;
+#[cfg(feature = "alloc")]
+mod hex;
+#[cfg(feature = "mmap")]
+mod mapped;
+#[cfg(feature = "std")]
 mod marked;
+#[cfg(feature = "rayon")]
+mod parallel;
+mod policy;
+#[cfg(feature = "futures")]
+mod sink;
+#[cfg(feature = "alloc")]
+mod spec;
+#[cfg(feature = "alloc")]
+mod split;
+#[cfg(feature = "futures")]
+mod stream;
@@ -0,0 +1,183 @@
+//! ## MappedMarked
+//!
+//! Memory-mapped access to marked files - frames are borrowed straight from the
+//! mapping, and a file can be cut into mark aligned ranges for parallel workers.
+
+use crate::{Boundary, Byte, ByteMarker, MarkerConfig, Split};
+use memmap2::Mmap;
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+
+/// A marked file mapped into memory.
+pub struct MappedMarked {
+    map: Mmap,
+    marker: ByteMarker,
+}
+
+impl MappedMarked {
+    pub fn open<P: AsRef<Path>>(path: P, mark: &str, tail: &str) -> io::Result<Self> {
+        Self::from_file(&File::open(path)?, ByteMarker::new(mark, tail))
+    }
+
+    pub fn open_with_config<P: AsRef<Path>>(path: P, config: &MarkerConfig) -> io::Result<Self> {
+        Self::from_file(&File::open(path)?, ByteMarker::from_config(config))
+    }
+
+    /// The file must not be truncated or modified by anyone while it is mapped.
+    pub fn from_file(file: &File, marker: ByteMarker) -> io::Result<Self> {
+        //SAFETY: the mapping is read only - soundness relies on the file not being
+        //changed underneath it, as documented above
+        let map = unsafe { Mmap::map(file)? };
+        Ok(Self { map, marker })
+    }
+
+    /// The whole mapped file.
+    pub fn bytes(&self) -> &[Byte] {
+        &self.map
+    }
+
+    /// Frames of the file as slices of the mapping - nothing is copied.
+    pub fn frames(&self) -> Split<'_, '_> {
+        self.marker.split(&self.map)
+    }
+
+    /// Plans at most `n` ranges covering the file, each starting and ending on a
+    /// frame boundary, so they can be unmarked independently of each other.
+    ///
+    /// Only the bytes from each step to the next mark are scanned, so a tail
+    /// elsewhere goes unnoticed. Unmark each range with `ByteMarker::split`, which
+    /// stops at the tail, and drop the ranges after the first one to end in a
+    /// `Segment::Tail`.
+    pub fn partition(&self, n: usize) -> Vec<Range<usize>> {
+        self.marker.partition(&self.map, n)
+    }
+}
+
+/// Maps `file` and plans at most `n` mark aligned ranges covering it - see
+/// `MappedMarked::partition`.
+pub fn partition(file: &File, marker: &ByteMarker, n: usize) -> io::Result<Vec<Range<usize>>> {
+    //SAFETY: the mapping is read only and dropped before returning
+    let map = unsafe { Mmap::map(file)? };
+    Ok(marker.partition(&map, n))
+}
+
+impl ByteMarker {
+    //Cuts just after the first mark at or past each `len / n` step, scanning only
+    //from one step to the next mark. A tail found that way ends the last range.
+    pub(crate) fn partition(&self, bytes: &[Byte], n: usize) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        if n == 0 {
+            return ranges;
+        }
+        let mut start = 0;
+        for step in 1..n {
+            let target = (bytes.len() / n) * step;
+            if target < start {
+                continue;
+            }
+            //Pick up a mark that started before the target but ends at or after it
+            let from = target.saturating_sub(self.partial_len() + 1).max(start);
+            match self.find_boundary(bytes, from) {
+                Some((index, boundary)) => {
+                    let end = index + self.boundary_len(boundary);
+                    ranges.push(start..end);
+                    start = end;
+                    if boundary == Boundary::Tail {
+                        return ranges;
+                    }
+                }
+                None => break,
+            }
+        }
+        if start < bytes.len() {
+            ranges.push(start..bytes.len());
+        }
+        ranges
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Segment;
+    use std::io::Write;
+
+    fn marked_file(marker: &ByteMarker, frames: usize) -> tempfile::NamedTempFile {
+        let payloads = (0..frames).map(|i| format!("frame number {}", i).repeat(i % 7));
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&marker.mark_all(payloads, false)).unwrap();
+        file.flush().unwrap();
+        file
+    }
+
+    #[test]
+    fn test_frames_borrow_from_mapping() {
+        let marker = ByteMarker::new("sUfFiX", "tAiL");
+        let file = marked_file(&marker, 100);
+        let mapped = MappedMarked::open(file.path(), "sUfFiX", "tAiL").unwrap();
+        let within = mapped.bytes().as_ptr_range();
+        let frames: Vec<_> = mapped.frames().collect();
+        assert_eq!(frames.len(), 100);
+        for (i, frame) in frames.iter().enumerate() {
+            assert!(matches!(frame, Segment::Frame(_)));
+            assert_eq!(
+                frame.bytes(),
+                format!("frame number {}", i).repeat(i % 7).as_bytes()
+            );
+            assert!(frame.bytes().is_empty() || within.contains(&frame.bytes().as_ptr()));
+        }
+    }
+
+    #[test]
+    fn test_partition_is_mark_aligned() {
+        let marker = ByteMarker::new("sUfFiX", "tAiL");
+        let file = marked_file(&marker, 500);
+        let mapped = MappedMarked::open(file.path(), "sUfFiX", "tAiL").unwrap();
+        let bytes = mapped.bytes();
+        let (all, _) = marker.unmark(bytes).unwrap();
+
+        for n in [1, 2, 3, 8, 64, 10_000] {
+            let ranges = partition(file.as_file(), &marker, n).unwrap();
+            assert_eq!(ranges, mapped.partition(n));
+            assert!(ranges.len() <= n);
+            assert_eq!(ranges.first().unwrap().start, 0);
+            assert_eq!(ranges.last().unwrap().end, bytes.len());
+            let mut frames = Vec::new();
+            for (range, next) in ranges.iter().zip(ranges.iter().skip(1)) {
+                assert_eq!(range.end, next.start);
+                assert!(bytes[range.clone()].ends_with(b"sUfFiX"));
+            }
+            for range in ranges {
+                let (unmarked, left_over) = marker.unmark(&bytes[range]).unwrap();
+                assert_eq!(left_over, None);
+                frames.extend(unmarked);
+            }
+            assert_eq!(frames, all);
+        }
+    }
+
+    #[test]
+    fn test_partition_stops_at_tail() {
+        let marker = ByteMarker::new("sUfFiX", "tAiL");
+        let bytes = b"onesUfFiXtwotAiLthreesUfFiXfoursUfFiX";
+        assert_eq!(marker.partition(bytes, 4), vec![0..9, 9..16]);
+        assert!(marker.partition(b"", 4).is_empty());
+        assert!(marker.partition(bytes, 0).is_empty());
+
+        //A tail before the first step is left to whoever unmarks the ranges
+        let bytes = b"onetAiLtwosUfFiXthreesUfFiXfoursUfFiX";
+        let ranges = marker.partition(bytes, 2);
+        assert_eq!(ranges, vec![0..27, 27..37]);
+        let mut frames = Vec::new();
+        for range in ranges {
+            let segments: Vec<_> = marker.split(&bytes[range]).collect();
+            frames.extend(segments.iter().map(Segment::bytes));
+            if let Some(Segment::Tail(_)) = segments.last() {
+                break;
+            }
+        }
+        assert_eq!(frames, marker.unmark(bytes).unwrap().0);
+    }
+}
@@ -1,26 +1,65 @@
 //! ## Marked
 //!
 
-use crate::{Byte, ByteMarks, MARK, TAIL};
+#[cfg(feature = "aead")]
+use crate::Decryptor;
+#[cfg(feature = "hmac")]
+use crate::Verifier;
+use crate::{
+    Boundary, Byte, ByteMarker, Checksum, Error, Frame, MarkerConfig, OverflowPolicy, Result,
+    TailMode, Terminator,
+};
 use std::io::BufRead;
 
+/// Items produced by `Marked::next_event`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// Bytes demarcated by a mark, the tail or the end of the stream.
+    Frame(Vec<Byte>),
+    /// The tail was seen - follows the frame that preceded it.
+    BatchEnd,
+}
+
 pub struct Marked<'a, R>
 where
     R: BufRead,
 {
     reader: &'a mut R,
-    initializer: ByteMarks<'a>,
-    marks: ByteMarks<'a>,
-    tail: Option<ByteMarks<'a>>,
+    marker: ByteMarker,
     bytes_fetched: usize,
-    curr_buf: Option<Vec<Byte>>,
-    left_over: Option<Vec<Byte>>,
-    buf_pos: usize,
+    frame_start: usize,
+    last_frame: (usize, Terminator),
+    pending: usize,
+    left_over: Vec<Byte>,
+    spilled: bool,
     eof_reached: bool,
-    start_byte: Byte,
-    mark_size: usize,
-    tail_start_byte: Byte,
-    tail_size: usize,
+    max_frame_len: Option<usize>,
+    max_stream_len: Option<usize>,
+    overflow_policy: OverflowPolicy,
+    discarding: bool,
+    tail_mode: TailMode,
+    batch_ended: bool,
+    strict: bool,
+    checks: Checks,
+}
+
+//Checks applied to each frame on its way out, kept apart from the reader so that
+//they can run on frames borrowed from its buffer
+#[derive(Default)]
+struct Checks {
+    checksum: Option<Checksum>,
+    #[cfg(feature = "hmac")]
+    verifier: Option<Verifier>,
+    #[cfg(feature = "aead")]
+    decryptor: Option<Decryptor>,
+    frames_read: usize,
+}
+
+//Where the frame handed out by `Marked::next_frame` lives
+enum Slot {
+    Buffered(usize),
+    Spilled,
+    BatchEnd,
 }
 
 impl<'a, R> Marked<'a, R>
@@ -28,122 +67,426 @@ where
     R: BufRead,
 {
     pub fn with_defaults(r: &'a mut R) -> Self {
-        Self::new(r, &MARK, &TAIL)
-    }
-    pub fn new(r: &'a mut R, mark: &'a str, tail: &'a str) -> Self {
-        let initializer = ByteMarks::initialize(mark, tail);
-        let marks = initializer.init_marking_indices();
-        let tail = if initializer.tail_bytes_len() > 0 {
-            Some(initializer.init_tail_indices())
-        } else {
-            None
-        };
+        Self::from_marker(r, ByteMarker::with_defaults())
+    }
+    pub fn new(r: &'a mut R, mark: &str, tail: &str) -> Self {
+        Self::from_marker(r, ByteMarker::new(mark, tail))
+    }
 
-        let start_byte = initializer.marking_start_byte();
-        let mark_size = initializer.marking_bytes_len();
-        let (tail_start_byte, tail_size) = if tail.is_some() {
-            (initializer.tail_start_byte(), initializer.tail_bytes_len())
-        } else {
-            (0, 0)
-        };
+    /// Takes the mark, tail, limits, overflow policy, tail mode, strictness and
+    /// checksum from `config`.
+    pub fn from_config(r: &'a mut R, config: &MarkerConfig) -> Self {
+        let mut marked = Self::from_marker(r, ByteMarker::from_config(config));
+        marked.max_frame_len = config.max_frame_len;
+        marked.max_stream_len = config.max_stream_len;
+        marked.overflow_policy = config.overflow_policy;
+        marked.tail_mode = config.tail_mode;
+        marked.strict = config.strict;
+        marked.checks.checksum = config.checksum;
+        marked
+    }
+
+    pub fn from_marker(r: &'a mut R, marker: ByteMarker) -> Self {
         Self {
             reader: r,
-            initializer,
-            marks,
-            tail,
+            marker,
             bytes_fetched: 0,
-            curr_buf: None,
-            left_over: None,
-            buf_pos: 0,
+            frame_start: 0,
+            last_frame: (0, Terminator::Eof),
+            pending: 0,
+            left_over: Vec::new(),
+            spilled: false,
             eof_reached: false,
-            start_byte,
-            mark_size,
-            tail_start_byte,
-            tail_size,
+            max_frame_len: None,
+            max_stream_len: None,
+            overflow_policy: OverflowPolicy::default(),
+            discarding: false,
+            tail_mode: TailMode::default(),
+            batch_ended: false,
+            strict: false,
+            checks: Checks::default(),
         }
     }
-}
 
-impl<'a, R> Iterator for Marked<'a, R>
-where
-    R: BufRead,
-{
-    type Item = Vec<Byte>;
-    fn next(&mut self) -> Option<Self::Item> {
+    /// Frames longer than `len` bytes are reported as `Error::FrameTooLarge`
+    /// instead of being buffered.
+    pub fn max_frame_len(mut self, len: usize) -> Self {
+        self.max_frame_len = Some(len);
+        self
+    }
+
+    /// Reading stops with `Error::StreamTooLarge` once the frames and marks taken
+    /// off the reader come to more than `len` bytes. Bytes the reader has buffered
+    /// ahead do not count.
+    pub fn max_stream_len(mut self, len: usize) -> Self {
+        self.max_stream_len = Some(len);
+        self
+    }
+
+    /// Sets what happens after a frame exceeds the maximum frame length.
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow_policy = policy;
+        self
+    }
+
+    /// Sets whether the tail ends the stream or just the current batch.
+    pub fn tail_mode(mut self, mode: TailMode) -> Self {
+        self.tail_mode = mode;
+        self
+    }
+
+    /// In strict mode, bytes left without a mark or tail when the stream ends -
+    /// including a partial mark - are reported as `Error::Truncated` rather than
+    /// being returned as a frame.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Expects every frame to carry a `checksum`, as written by
+    /// `ByteMarker::mark_checked`. The checksum is stripped from frames that match
+    /// it, and those that do not are reported as `Error::ChecksumMismatch` - bytes
+    /// cut short by the end of the stream included.
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.checks.checksum = Some(checksum);
+        self
+    }
+
+    /// Expects every frame to be signed, as by `ByteMarker::mark_signed`, and checks
+    /// them in order with `verifier`. The key id and tag are stripped from frames
+    /// that pass, and those that fail are reported as `Error::Unauthenticated`. Bytes
+    /// cut short by the end of the stream are checked too. A frame carrying a
+    /// checksum as well is authenticated first.
+    ///
+    /// Panics if the mark or tail starts or ends with a lowercase hex digit - the
+    /// tag could run into it.
+    #[cfg(feature = "hmac")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "hmac")))]
+    pub fn verifier(mut self, verifier: Verifier) -> Self {
+        if let Err(err) = self.marker.check_hex_safe() {
+            panic!("{}", err);
+        }
+        self.checks.verifier = Some(verifier);
+        self
+    }
+
+    /// The verifier set with `verifier`, to add or remove keys as they rotate.
+    #[cfg(feature = "hmac")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "hmac")))]
+    pub fn verifier_mut(&mut self) -> Option<&mut Verifier> {
+        self.checks.verifier.as_mut()
+    }
+
+    /// Expects every frame to be encrypted, as by `ByteMarker::mark_encrypted`, and
+    /// decrypts them in order with `decryptor`. Frames that do not decrypt are
+    /// reported as `Error::Undecryptable`, and like unauthenticated ones, bytes cut
+    /// short by the end of the stream are not let through either. Signatures and
+    /// checksums, if any, are checked on the ciphertext first.
+    ///
+    /// Panics if the mark or tail starts or ends with a lowercase hex digit - the
+    /// ciphertext could run into it.
+    #[cfg(feature = "aead")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "aead")))]
+    pub fn decryptor(mut self, decryptor: Decryptor) -> Self {
+        if let Err(err) = self.marker.check_hex_safe() {
+            panic!("{}", err);
+        }
+        self.checks.decryptor = Some(decryptor);
+        self
+    }
+
+    /// Like `try_next`, but also reports every tail as `Event::BatchEnd`.
+    pub fn next_event(&mut self) -> Option<Result<Event>> {
+        match self.advance()? {
+            Ok(Slot::BatchEnd) => Some(Ok(Event::BatchEnd)),
+            Ok(Slot::Spilled) => Some(
+                self.checks
+                    .apply(&self.left_over, self.last_frame)
+                    .map(|frame| Event::Frame(frame.to_vec())),
+            ),
+            Ok(Slot::Buffered(len)) => match self.reader.fill_buf() {
+                Ok(buf) => Some(
+                    self.checks
+                        .apply(&buf[..len], self.last_frame)
+                        .map(|frame| Event::Frame(frame.to_vec())),
+                ),
+                Err(err) => Some(Err(Error::Io(err))),
+            },
+            Err(err) => Some(Err(err)),
+        }
+    }
+
+    /// Returns the next frame, or the error that prevented reading it. With
+    /// `OverflowPolicy::Discard`, reading may continue after a `FrameTooLarge`.
+    pub fn try_next(&mut self) -> Option<Result<Vec<Byte>>> {
+        self.next_frame().map(|frame| frame.map(<[Byte]>::to_vec))
+    }
+
+    /// Like `try_next`, but also reports where the frame starts in the stream and
+    /// whether it was ended by the mark, the tail or the end of the stream.
+    pub fn read_frame(&mut self) -> Option<Result<Frame>> {
+        let data = match self.next_frame()? {
+            Ok(data) => data.to_vec(),
+            Err(err) => return Some(Err(err)),
+        };
+        let (start_offset, terminator) = self.last_frame;
+        Some(Ok(Frame {
+            data,
+            start_offset,
+            terminator,
+        }))
+    }
+
+    /// Lending counterpart of `try_next`. The frame is borrowed straight from the
+    /// reader's buffer when it fits there - only frames spanning more than one
+    /// `fill_buf` are copied, into a buffer that is reused across calls.
+    pub fn next_frame(&mut self) -> Option<Result<&[Byte]>> {
         loop {
+            match self.advance()? {
+                Ok(Slot::BatchEnd) => continue,
+                Ok(Slot::Spilled) => {
+                    return Some(self.checks.apply(&self.left_over, self.last_frame))
+                }
+                //The bytes are still sitting unconsumed in the reader, so this does no I/O
+                Ok(Slot::Buffered(len)) => {
+                    return match self.reader.fill_buf() {
+                        Ok(buf) => Some(self.checks.apply(&buf[..len], self.last_frame)),
+                        Err(err) => Some(Err(Error::Io(err))),
+                    }
+                }
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+
+    fn advance(&mut self) -> Option<Result<Slot>> {
+        loop {
+            if self.pending > 0 {
+                self.reader.consume(self.pending);
+                self.pending = 0;
+            }
+            if self.spilled {
+                self.left_over.clear();
+                self.spilled = false;
+            }
+            if std::mem::take(&mut self.batch_ended) {
+                return Some(Ok(Slot::BatchEnd));
+            }
             if self.eof_reached {
                 return None;
             }
-            match self.curr_buf {
-                Some(ref bytes) => {
-                    let mut index = self.buf_pos;
-                    while index < bytes.len() {
-                        if bytes[index] == self.start_byte
-                            && self.initializer.marking_matches(
-                                &self.initializer,
-                                &self.marks,
-                                index,
-                                bytes,
-                            )
-                        {
-                            let next = Some(bytes[self.buf_pos..index].to_vec());
-                            self.buf_pos = index + self.mark_size;
-                            if self.buf_pos == bytes.len() {
-                                self.curr_buf = None;
-                            }
-                            return next;
+            let buf = match self.reader.fill_buf() {
+                Ok(buf) => buf,
+                Err(err) => {
+                    self.eof_reached = true;
+                    return Some(Err(Error::Io(err)));
+                }
+            };
+            if buf.is_empty() {
+                self.eof_reached = true;
+                if self.left_over.is_empty() || self.discarding {
+                    return None;
+                }
+                if self.strict {
+                    let bytes = self.left_over.len();
+                    self.left_over.clear();
+                    return Some(Err(Error::Truncated { bytes }));
+                }
+                if self.exceeds_frame_len(self.left_over.len()) {
+                    return Some(Err(self.frame_overflow()));
+                }
+                self.last_frame = (self.frame_start, Terminator::Eof);
+                self.spilled = true;
+                return Some(Ok(Slot::Spilled));
+            }
+            if self.left_over.is_empty() && !self.discarding {
+                match self.marker.find_boundary(buf, 0) {
+                    Some((index, boundary)) => {
+                        self.pending = index + self.marker.boundary_len(boundary);
+                        self.bytes_fetched += self.pending;
+                        if let Some(err) = self.stream_overflow() {
+                            return Some(Err(err));
                         }
-
-                        if let Some(ref tail) = self.tail {
-                            if bytes[index] == self.tail_start_byte
-                                && self.initializer.tail_marking_matches(
-                                    &self.initializer,
-                                    tail,
-                                    index,
-                                    bytes,
-                                )
-                            {
-                                let pre_tail = Some(bytes[self.buf_pos..index].to_vec());
-                                self.buf_pos = index + self.tail_size;
-                                self.eof_reached = true;
-                                self.curr_buf = None;
-                                return pre_tail;
-                            }
+                        self.last_frame = (self.frame_start, boundary.into());
+                        self.frame_start = self.bytes_fetched;
+                        if boundary == Boundary::Tail {
+                            self.end_batch();
+                        }
+                        if self.exceeds_frame_len(index) {
+                            return Some(Err(self.frame_overflow()));
                         }
-                        index += 1;
+                        return Some(Ok(Slot::Buffered(index)));
                     }
-
-                    if index == bytes.len() {
-                        self.left_over = Some(bytes[self.buf_pos..].to_vec());
-                        self.curr_buf = None;
+                    None => {
+                        let fetched = buf.len();
+                        self.left_over.extend(buf);
+                        self.consume(fetched);
                     }
                 }
-                None => match self.reader.fill_buf() {
-                    Ok(buf) if buf.is_empty() => {
-                        return self.left_over.take();
-                    }
-                    Ok(buf) => {
-                        self.bytes_fetched += buf.len();
-                        self.buf_pos = 0;
-                        if let Some(mut left_over) = self.left_over.take() {
-                            left_over.extend(buf);
-                            self.curr_buf = Some(left_over);
-                        } else {
-                            self.curr_buf = Some(buf.to_vec());
+            } else {
+                //A mark may straddle what was spilled and what just arrived
+                let spilled = self.left_over.len();
+                self.left_over.extend(buf);
+                let from = spilled.saturating_sub(self.marker.partial_len());
+                match self.marker.find_boundary(&self.left_over, from) {
+                    Some((index, boundary)) => {
+                        let end = index + self.marker.boundary_len(boundary);
+                        self.consume(end - spilled);
+                        if let Some(err) = self.stream_overflow() {
+                            return Some(Err(err));
+                        }
+                        self.left_over.truncate(index);
+                        self.last_frame = (self.frame_start, boundary.into());
+                        self.frame_start = self.bytes_fetched;
+                        if boundary == Boundary::Tail {
+                            self.end_batch();
+                        }
+                        if std::mem::replace(&mut self.discarding, false) {
+                            self.left_over.clear();
+                            continue;
+                        }
+                        if self.exceeds_frame_len(index) {
+                            self.left_over.clear();
+                            return Some(Err(self.frame_overflow()));
                         }
-                        self.reader.consume(self.bytes_fetched);
+                        self.spilled = true;
+                        return Some(Ok(Slot::Spilled));
                     }
-                    Err(err) => {
-                        eprintln!("Error filling buf = {:?}", err);
-                        return None;
+                    None => {
+                        self.consume(self.left_over.len() - spilled);
                     }
-                },
+                }
+            }
+
+            if let Some(err) = self.stream_overflow() {
+                return Some(Err(err));
+            }
+            if self.discarding {
+                self.trim_left_over();
+            } else if self.left_over_overflows() {
+                return Some(Err(self.left_over_overflow()));
+            }
+        }
+    }
+
+    fn end_batch(&mut self) {
+        self.batch_ended = true;
+        if self.tail_mode == TailMode::Stop {
+            self.eof_reached = true;
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.reader.consume(amt);
+        self.bytes_fetched += amt;
+    }
+
+    //Only bytes taken off the reader count - not what it has read ahead
+    fn stream_overflow(&mut self) -> Option<Error> {
+        let limit = self
+            .max_stream_len
+            .filter(|limit| self.bytes_fetched > *limit)?;
+        self.eof_reached = true;
+        self.left_over.clear();
+        Some(Error::StreamTooLarge { limit })
+    }
+
+    fn exceeds_frame_len(&self, len: usize) -> bool {
+        self.max_frame_len.is_some_and(|limit| len > limit)
+    }
+
+    fn left_over_overflows(&self) -> bool {
+        self.max_frame_len.is_some_and(|limit| {
+            self.left_over.len() > limit.saturating_add(self.marker.partial_len())
+        })
+    }
+
+    fn trim_left_over(&mut self) {
+        let keep = self.marker.partial_len();
+        if self.left_over.len() > keep {
+            self.left_over.drain(..self.left_over.len() - keep);
+        }
+    }
+
+    fn frame_overflow(&mut self) -> Error {
+        if self.overflow_policy == OverflowPolicy::Abort {
+            self.eof_reached = true;
+        }
+        Error::FrameTooLarge {
+            limit: self.max_frame_len.unwrap_or_default(),
+        }
+    }
+
+    fn left_over_overflow(&mut self) -> Error {
+        match self.overflow_policy {
+            OverflowPolicy::Abort => self.left_over.clear(),
+            OverflowPolicy::Discard => {
+                self.discarding = true;
+                self.trim_left_over();
+            }
+        }
+        self.frame_overflow()
+    }
+}
+
+impl Checks {
+    //Strips the tag and checksum off the next frame and decrypts it, failing if
+    //any of them does not check out
+    fn apply<'f>(
+        &'f mut self,
+        frame: &'f [Byte],
+        (offset, _): (usize, Terminator),
+    ) -> Result<&'f [Byte]> {
+        let frame_index = self.frames_read;
+        self.frames_read += 1;
+        #[cfg(feature = "hmac")]
+        let frame = match &mut self.verifier {
+            Some(verifier) => verifier
+                .verify(frame)
+                .map_err(|reason| Error::Unauthenticated {
+                    frame_index,
+                    offset,
+                    reason,
+                })?,
+            None => frame,
+        };
+        let frame = match self.checksum {
+            Some(checksum) => checksum.verify(frame).ok_or(Error::ChecksumMismatch {
+                frame_index,
+                offset,
+            })?,
+            None => frame,
+        };
+        #[cfg(feature = "aead")]
+        if let Some(decryptor) = &mut self.decryptor {
+            return decryptor.decrypt(frame).map_err(|_| Error::Undecryptable {
+                frame_index,
+                offset,
+            });
+        }
+        Ok(frame)
+    }
+}
+
+impl<'a, R> Iterator for Marked<'a, R>
+where
+    R: BufRead,
+{
+    type Item = Vec<Byte>;
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.try_next()? {
+            Ok(bytes) => Some(bytes),
+            //Use `try_next` to see the error
+            Err(_) => {
+                self.eof_reached = true;
+                None
             }
         }
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::items_after_test_module)]
 mod test {
     use super::*;
     use std::fs::File;
@@ -163,6 +506,7 @@ mod test {
         }
     }
     #[test]
+    #[allow(clippy::cmp_owned)]
     fn test_file_with_multiple_marks_with_mark_and_panic_handled() {
         let mut reader =
             BufReader::new(File::open("tests/file_with_multiple_marks_at_end.txt").unwrap());
@@ -263,6 +607,7 @@ mod test {
         }
     }
     #[test]
+    #[allow(clippy::vec_init_then_push)]
     fn test_stream_with_marks_and_tail() {
         let message =
             "This issUfFiX a msgsUfFiX with interspercedsUfFiX with suffixes and finally atAiL";
@@ -280,8 +625,458 @@ mod test {
             assert!(unmarked == segment.to_vec());
         }
     }
-}
 
+    #[test]
+    fn test_frame_too_large_aborts() {
+        let mut cursor = Cursor::new("smallsUfFiXway too largesUfFiXsmallsUfFiX".as_bytes());
+        let mut marked = Marked::new(&mut cursor, "sUfFiX", "").max_frame_len(5);
+        assert_eq!(marked.try_next().unwrap().unwrap(), b"small");
+        assert!(matches!(
+            marked.try_next(),
+            Some(Err(Error::FrameTooLarge { limit: 5 }))
+        ));
+        assert!(marked.try_next().is_none());
+    }
+
+    #[test]
+    fn test_frame_too_large_discards_up_to_next_mark() -> Result<()> {
+        let message = "smallsUfFiXway too large and never endingsUfFiXnextsUfFiX";
+        let mut reader = BufReader::with_capacity(4, Cursor::new(message.as_bytes()));
+        let mut marked = Marked::new(&mut reader, "sUfFiX", "")
+            .max_frame_len(5)
+            .overflow_policy(OverflowPolicy::Discard);
+        assert_eq!(marked.try_next().unwrap().unwrap(), b"small");
+        assert!(matches!(
+            marked.try_next(),
+            Some(Err(Error::FrameTooLarge { limit: 5 }))
+        ));
+        assert_eq!(marked.try_next().unwrap().unwrap(), b"next");
+        assert!(marked.try_next().is_none());
+
+        let mut reader = BufReader::with_capacity(4, Cursor::new(message.as_bytes()));
+        let marked = Marked::new(&mut reader, "sUfFiX", "")
+            .max_frame_len(5)
+            .overflow_policy(OverflowPolicy::Discard);
+        //The iterator stops at the first error
+        assert_eq!(marked.collect::<Vec<_>>(), vec![b"small".to_vec()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unterminated_stream_does_not_grow_unbounded() {
+        let endless = vec![b'x'; 64 * 1024];
+        let mut reader = BufReader::with_capacity(16, Cursor::new(endless));
+        let mut marked = Marked::new(&mut reader, "sUfFiX", "")
+            .max_frame_len(1024)
+            .overflow_policy(OverflowPolicy::Discard);
+        assert!(matches!(
+            marked.try_next(),
+            Some(Err(Error::FrameTooLarge { limit: 1024 }))
+        ));
+        assert!(marked.left_over.len() < 1024);
+        assert!(marked.try_next().is_none());
+    }
+
+    #[test]
+    fn test_next_frame_borrows_from_reader_buffer() {
+        let message = "StreamingsUfFiX withsUfFiX markssUfFiX and tailtAiL".as_bytes();
+        let within = message.as_ptr_range();
+        let mut cursor = Cursor::new(message);
+        let mut marked = Marked::new(&mut cursor, "sUfFiX", "tAiL");
+        let mut frames = Vec::new();
+        while let Some(frame) = marked.next_frame() {
+            let frame = frame.unwrap();
+            assert!(within.contains(&frame.as_ptr()));
+            frames.push(String::from_utf8(frame.to_vec()).unwrap());
+        }
+        assert_eq!(frames, ["Streaming", " with", " marks", " and tail"]);
+    }
+
+    #[test]
+    fn test_next_frame_across_chunk_boundaries() {
+        let message = "StreamingsUfFiX withsUfFiX markssUfFiXsUfFiX and tailtAiLignored";
+        let expected = ["Streaming", " with", " marks", "", " and tail"];
+        for capacity in 1..message.len() + 1 {
+            let mut reader = BufReader::with_capacity(capacity, Cursor::new(message.as_bytes()));
+            let mut marked = Marked::new(&mut reader, "sUfFiX", "tAiL");
+            let mut frames = Vec::new();
+            while let Some(frame) = marked.next_frame() {
+                frames.push(String::from_utf8(frame.unwrap().to_vec()).unwrap());
+            }
+            assert_eq!(frames, expected, "capacity {}", capacity);
+        }
+    }
+
+    #[test]
+    fn test_batches_continue_past_tail() {
+        let message = "onesUfFiXtwotAiLthreesUfFiXfourtAiLfive";
+        for capacity in [1, 5, message.len()] {
+            let mut reader = BufReader::with_capacity(capacity, Cursor::new(message.as_bytes()));
+            let mut marked =
+                Marked::new(&mut reader, "sUfFiX", "tAiL").tail_mode(TailMode::BatchEnd);
+            let mut events = Vec::new();
+            while let Some(event) = marked.next_event() {
+                events.push(event.unwrap());
+            }
+            let frame = |s: &str| Event::Frame(s.as_bytes().to_vec());
+            assert_eq!(
+                events,
+                vec![
+                    frame("one"),
+                    frame("two"),
+                    Event::BatchEnd,
+                    frame("three"),
+                    frame("four"),
+                    Event::BatchEnd,
+                    frame("five"),
+                ]
+            );
+        }
+
+        let mut cursor = Cursor::new(message.as_bytes());
+        let marked = Marked::new(&mut cursor, "sUfFiX", "tAiL").tail_mode(TailMode::BatchEnd);
+        assert_eq!(marked.count(), 5);
+    }
+
+    #[test]
+    fn test_tail_stops_by_default() {
+        let mut cursor = Cursor::new("onetAiLtwosUfFiX".as_bytes());
+        let mut marked = Marked::new(&mut cursor, "sUfFiX", "tAiL");
+        assert_eq!(
+            marked.next_event().unwrap().unwrap(),
+            Event::Frame(b"one".to_vec())
+        );
+        assert_eq!(marked.next_event().unwrap().unwrap(), Event::BatchEnd);
+        assert!(marked.next_event().is_none());
+        assert_eq!(cursor.position(), 7);
+    }
+
+    #[test]
+    fn test_read_frame_reports_offsets_and_terminators() {
+        let message = "onesUfFiXtwotAiLthreesUfFiXfour";
+        let frame = |data: &str, start_offset, terminator| Frame {
+            data: data.as_bytes().to_vec(),
+            start_offset,
+            terminator,
+        };
+        for capacity in [1, 4, message.len()] {
+            let mut reader = BufReader::with_capacity(capacity, Cursor::new(message.as_bytes()));
+            let mut marked =
+                Marked::new(&mut reader, "sUfFiX", "tAiL").tail_mode(TailMode::BatchEnd);
+            let mut frames = Vec::new();
+            while let Some(frame) = marked.read_frame() {
+                frames.push(frame.unwrap());
+            }
+            assert_eq!(
+                frames,
+                vec![
+                    frame("one", 0, Terminator::Mark),
+                    frame("two", 9, Terminator::Tail),
+                    frame("three", 16, Terminator::Mark),
+                    frame("four", 27, Terminator::Eof),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_strict_rejects_unterminated_bytes() {
+        for (message, bytes) in [("onesUfFiXtwosUf", 6), ("onesUfFiXtwotA", 5)] {
+            let mut reader = BufReader::with_capacity(3, Cursor::new(message.as_bytes()));
+            let mut marked = Marked::new(&mut reader, "sUfFiX", "tAiL").strict(true);
+            assert_eq!(marked.try_next().unwrap().unwrap(), b"one");
+            assert!(matches!(
+                marked.try_next(),
+                Some(Err(Error::Truncated { bytes: b })) if b == bytes
+            ));
+            assert!(marked.try_next().is_none());
+        }
+
+        let mut cursor = Cursor::new("onesUfFiXtwotAiL".as_bytes());
+        let marked = Marked::new(&mut cursor, "sUfFiX", "tAiL").strict(true);
+        assert_eq!(marked.count(), 2);
+    }
+
+    #[test]
+    fn test_stream_too_large() {
+        let mut reader = BufReader::with_capacity(9, Cursor::new("onesUfFiXtwosUfFiXthreesUfFiX"));
+        let mut marked = Marked::new(&mut reader, "sUfFiX", "").max_stream_len(10);
+        assert_eq!(marked.try_next().unwrap().unwrap(), b"one");
+        assert!(matches!(
+            marked.try_next(),
+            Some(Err(Error::StreamTooLarge { limit: 10 }))
+        ));
+        assert!(marked.try_next().is_none());
+
+        //What the reader has read ahead is not counted
+        let mut cursor = Cursor::new("onesUfFiXtwosUfFiXthreesUfFiX");
+        let mut marked = Marked::new(&mut cursor, "sUfFiX", "").max_stream_len(18);
+        assert_eq!(marked.try_next().unwrap().unwrap(), b"one");
+        assert_eq!(marked.try_next().unwrap().unwrap(), b"two");
+        assert!(matches!(
+            marked.try_next(),
+            Some(Err(Error::StreamTooLarge { limit: 18 }))
+        ));
+    }
+
+    #[test]
+    fn test_from_config_applies_settings() {
+        let config = MarkerConfig::new("sUfFiX", "tAiL")
+            .unwrap()
+            .max_frame_len(5)
+            .overflow_policy(OverflowPolicy::Discard)
+            .tail_mode(TailMode::BatchEnd)
+            .strict(true);
+        let mut cursor = Cursor::new("onesUfFiXway too longsUfFiXtwotAiLthree");
+        let mut marked = Marked::from_config(&mut cursor, &config);
+        assert_eq!(marked.try_next().unwrap().unwrap(), b"one");
+        assert!(matches!(
+            marked.try_next(),
+            Some(Err(Error::FrameTooLarge { limit: 5 }))
+        ));
+        assert_eq!(marked.try_next().unwrap().unwrap(), b"two");
+        assert!(matches!(
+            marked.try_next(),
+            Some(Err(Error::Truncated { bytes: 5 }))
+        ));
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let marker = ByteMarker::new("sUfFiX", "tAiL");
+        let mut bytes = Vec::new();
+        for payload in [&b"one"[..], b"two", b"three"] {
+            marker.mark_checked(&mut bytes, payload, Checksum::Crc32);
+        }
+        bytes.extend(b"four");
+        Checksum::Crc32.append(b"four", &mut bytes);
+        marker.mark_tail(&mut bytes);
+        //Flip a bit in the second frame
+        bytes[18] ^= 0x20;
+
+        let mut cursor = Cursor::new(&bytes);
+        let mut marked = Marked::from_marker(&mut cursor, marker.clone()).checksum(Checksum::Crc32);
+        assert_eq!(marked.try_next().unwrap().unwrap(), b"one");
+        assert!(matches!(
+            marked.try_next(),
+            Some(Err(Error::ChecksumMismatch {
+                frame_index: 1,
+                offset: 17
+            }))
+        ));
+        assert_eq!(marked.try_next().unwrap().unwrap(), b"three");
+        let frame = marked.read_frame().unwrap().unwrap();
+        assert_eq!(frame.data, b"four");
+        assert_eq!(frame.terminator, Terminator::Tail);
+        assert!(marked.try_next().is_none());
+
+        //A frame cut short by the end of the stream is not let through with its
+        //checksum digits
+        let mut bytes = Vec::new();
+        marker.mark_checked(&mut bytes, b"one", Checksum::Crc16);
+        bytes.extend(b"twoffff");
+        let mut cursor = Cursor::new(&bytes);
+        let mut marked = Marked::from_marker(&mut cursor, marker).checksum(Checksum::Crc16);
+        assert_eq!(marked.try_next().unwrap().unwrap(), b"one");
+        assert!(matches!(
+            marked.try_next(),
+            Some(Err(Error::ChecksumMismatch {
+                frame_index: 1,
+                offset: 13
+            }))
+        ));
+        assert!(marked.try_next().is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "hmac")]
+    fn test_signed_frames() {
+        use crate::{AuthFailure, Signer};
+
+        let marker = ByteMarker::new("sUfFiX", "tAiL");
+        let mut signer = Signer::new(1, b"session key");
+        let mut bytes = Vec::new();
+        marker.mark_signed(&mut bytes, b"one", &mut signer).unwrap();
+        let replayed = bytes.clone();
+        //A forged frame, then the first replayed, between the genuine ones
+        marker
+            .mark_signed(&mut bytes, b"forged", &mut Signer::new(1, b"guess"))
+            .unwrap();
+        bytes.extend(&replayed);
+        marker.mark_signed(&mut bytes, b"two", &mut signer).unwrap();
+        bytes.extend(b"unsigned");
+
+        let mut cursor = Cursor::new(&bytes);
+        let verifier = Verifier::new().with_key(1, b"session key");
+        let mut marked = Marked::from_marker(&mut cursor, marker).verifier(verifier);
+        assert_eq!(marked.try_next().unwrap().unwrap(), b"one");
+        for offset in [75, 153] {
+            assert!(matches!(
+                marked.try_next(),
+                Some(Err(Error::Unauthenticated {
+                    offset: o,
+                    reason: AuthFailure::BadTag,
+                    ..
+                })) if o == offset
+            ));
+        }
+        assert_eq!(marked.try_next().unwrap().unwrap(), b"two");
+        assert!(matches!(
+            marked.try_next(),
+            Some(Err(Error::Unauthenticated {
+                frame_index: 4,
+                reason: AuthFailure::Malformed,
+                ..
+            }))
+        ));
+        assert_eq!(marked.verifier_mut().unwrap().sequence(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "hmac")]
+    #[should_panic]
+    fn test_verifier_rejects_hex_tail() {
+        let mut cursor = Cursor::new(Vec::new());
+        let _ = Marked::new(&mut cursor, "sUfFiX", "tAiL0").verifier(Verifier::new());
+    }
+
+    #[test]
+    #[cfg(feature = "aead")]
+    #[should_panic]
+    fn test_decryptor_rejects_hex_mark() {
+        let key = [7; 32];
+        let mut cursor = Cursor::new(Vec::new());
+        let _ = Marked::new(&mut cursor, "beef", "").decryptor(Decryptor::new(
+            crate::Cipher::Aes256Gcm,
+            &key,
+            1,
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "aead")]
+    fn test_encrypted_frames() {
+        use crate::{Cipher, Encryptor};
+
+        let key = [7; 32];
+        let marker = ByteMarker::new("sUfFiX", "tAiL");
+        let mut encryptor = Encryptor::new(Cipher::Aes256Gcm, &key, 1);
+        let mut bytes = Vec::new();
+        marker
+            .mark_encrypted(&mut bytes, b"one", &mut encryptor)
+            .unwrap();
+        let first_len = bytes.len();
+        marker
+            .mark_encrypted(&mut bytes, b"two", &mut encryptor)
+            .unwrap();
+        //The second frame again, now out of order
+        bytes.extend_from_within(first_len..);
+        marker
+            .mark_encrypted(&mut bytes, b"three", &mut encryptor)
+            .unwrap();
+        bytes.extend(b"plain");
+
+        let mut cursor = Cursor::new(&bytes);
+        let decryptor = Decryptor::new(Cipher::Aes256Gcm, &key, 1);
+        let mut marked = Marked::from_marker(&mut cursor, marker).decryptor(decryptor);
+        assert_eq!(marked.next_frame().unwrap().unwrap(), b"one");
+        assert_eq!(marked.try_next().unwrap().unwrap(), b"two");
+        assert!(matches!(
+            marked.try_next(),
+            Some(Err(Error::Undecryptable {
+                frame_index: 2,
+                offset: 88
+            }))
+        ));
+        assert_eq!(
+            marked.next_event().unwrap().unwrap(),
+            Event::Frame(b"three".to_vec())
+        );
+        assert!(matches!(
+            marked.try_next(),
+            Some(Err(Error::Undecryptable { frame_index: 4, .. }))
+        ));
+    }
+
+    //Reads `input` through `Marked`, `capacity` bytes at a time, until it stops
+    fn read_marked(input: &[u8], capacity: usize, config: &MarkerConfig) -> Vec<String> {
+        let mut reader = BufReader::with_capacity(capacity, Cursor::new(input));
+        let mut marked = Marked::from_config(&mut reader, config);
+        let mut outcomes = Vec::new();
+        while let Some(frame) = marked.read_frame() {
+            outcomes.push(format!("{:?}", frame));
+        }
+        outcomes
+    }
+
+    //Pushes `input` into a `Decoder` in chunks of `capacity` bytes
+    fn read_decoded(input: &[u8], capacity: usize, config: &MarkerConfig) -> Vec<String> {
+        let mut decoder = crate::Decoder::from_config(config);
+        let mut outcomes = Vec::new();
+        for chunk in input.chunks(capacity) {
+            decoder.push(chunk);
+            while let Some(frame) = decoder.next_frame() {
+                outcomes.push(format!("{:?}", frame));
+            }
+        }
+        decoder.finish();
+        while let Some(frame) = decoder.next_frame() {
+            outcomes.push(format!("{:?}", frame));
+        }
+        outcomes
+    }
+
+    #[test]
+    fn test_marked_and_decoder_agree() {
+        let marker = ByteMarker::new("sUfFiX", "tAiL");
+        let mut checked = Vec::new();
+        for payload in [&b"one"[..], b"way too long", b"", b"two"] {
+            marker.mark_checked(&mut checked, payload, Checksum::Crc32);
+        }
+        checked.extend_from_slice(b"threetAiLfour");
+        checked[20] ^= 1;
+        let inputs: [&[u8]; 5] = [
+            b"",
+            b"onesUfFiXway too longsUfFiXsUfFiXtwotAiLthreesUfFiXfour",
+            b"way too long and never endingsUfFiXnexttAiLsUfFi",
+            b"onesUfFiXtwosUfFiXthreesUfFiX",
+            &checked,
+        ];
+        for input in inputs {
+            for capacity in 1..input.len() + 2 {
+                for setting in 0..64 {
+                    let mut config = MarkerConfig::new("sUfFiX", "tAiL").unwrap();
+                    if setting & 1 != 0 {
+                        config = config.max_frame_len(5);
+                    }
+                    if setting & 2 != 0 {
+                        config = config.max_stream_len(20);
+                    }
+                    if setting & 4 != 0 {
+                        config = config.overflow_policy(OverflowPolicy::Discard);
+                    }
+                    if setting & 8 != 0 {
+                        config = config.tail_mode(TailMode::BatchEnd);
+                    }
+                    if setting & 16 != 0 {
+                        config = config.strict(true);
+                    }
+                    if setting & 32 != 0 {
+                        config = config.checksum(Checksum::Crc32);
+                    }
+                    assert_eq!(
+                        read_marked(input, capacity, &config),
+                        read_decoded(input, capacity, &config),
+                        "input {:?}, capacity {}, setting {}",
+                        String::from_utf8_lossy(input),
+                        capacity,
+                        setting
+                    );
+                }
+            }
+        }
+    }
+}
 
 ///A convinient macros
 #[macro_export]
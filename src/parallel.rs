@@ -0,0 +1,129 @@
+//! ## Parallel unmarking
+//!
+//! Splits large in-memory buffers across the [rayon] thread pool.
+
+use crate::{Boundary, Byte, ByteMarker, Unmarked};
+use rayon::prelude::*;
+
+//Below this, handing chunks to other threads costs more than scanning them
+const MIN_CHUNK_LEN: usize = 64 * 1024;
+
+impl ByteMarker {
+    /// Parallel counterpart of `unmark` - the result is exactly what `unmark`
+    /// returns, tail handling included.
+    ///
+    /// The buffer is cut into chunks that are scanned for marks and tails on the
+    /// rayon thread pool. A mark straddling two chunks is found by the chunk it
+    /// starts in, which looks just far enough past its end to match it.
+    #[cfg_attr(docsrs, doc(cfg(feature = "rayon")))]
+    pub fn par_unmark<'b>(&self, bytes: &'b [Byte]) -> Option<Unmarked<'b>> {
+        let chunk_len = (bytes.len() / (rayon::current_num_threads() * 4)).max(MIN_CHUNK_LEN);
+        self.par_unmark_chunked(bytes, chunk_len)
+    }
+
+    pub(crate) fn par_unmark_chunked<'b>(
+        &self,
+        bytes: &'b [Byte],
+        chunk_len: usize,
+    ) -> Option<Unmarked<'b>> {
+        if bytes.is_empty() {
+            return None;
+        }
+        let chunks: Vec<Vec<(usize, Boundary)>> = (0..bytes.len())
+            .step_by(chunk_len)
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|start| self.boundaries_from(bytes, start, chunk_len))
+            .collect();
+
+        //Resolve overlaps in order, exactly as a sequential scan would
+        let mut unmarked = Vec::new();
+        let mut processed_bytes = 0;
+        for (index, boundary) in chunks.into_iter().flatten() {
+            if index < processed_bytes {
+                continue;
+            }
+            unmarked.push(&bytes[processed_bytes..index]);
+            processed_bytes = index + self.boundary_len(boundary);
+            if boundary == Boundary::Tail {
+                return Some((unmarked, None));
+            }
+        }
+        let left_over = Some(&bytes[processed_bytes..]).filter(|remained| !remained.is_empty());
+        Some((unmarked, left_over))
+    }
+
+    //Every position in `start..start + chunk_len` where a mark or tail begins
+    fn boundaries_from(
+        &self,
+        bytes: &[Byte],
+        start: usize,
+        chunk_len: usize,
+    ) -> Vec<(usize, Boundary)> {
+        let end = (start + chunk_len).min(bytes.len());
+        let window = &bytes[..(end + self.partial_len()).min(bytes.len())];
+        let mut boundaries = Vec::new();
+        let mut from = start;
+        while let Some((index, boundary)) = self.find_boundary(window, from) {
+            if index >= end {
+                break;
+            }
+            boundaries.push((index, boundary));
+            from = index + 1;
+        }
+        boundaries
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::Rng;
+
+    fn random_marked(marker: &ByteMarker, pieces: &[&str], count: usize) -> Vec<Byte> {
+        let mut randomizer = rand::thread_rng();
+        let mut bytes = Vec::new();
+        for _ in 0..count {
+            bytes.extend(pieces[randomizer.gen_range(0..pieces.len())].as_bytes());
+            if randomizer.gen_bool(0.3) {
+                marker.mark_bytes(&mut bytes);
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_matches_sequential_unmark() {
+        let marker = ByteMarker::new("sUfFiX", "tAiL");
+        let pieces = ["Some random", "strings", "sUf", "FiX", "tA", "i", "L", ""];
+        for _ in 0..50 {
+            let bytes = random_marked(&marker, &pieces, 200);
+            for chunk_len in [1, 2, 5, 7, 64, bytes.len().max(1)] {
+                assert_eq!(
+                    marker.par_unmark_chunked(&bytes, chunk_len),
+                    marker.unmark(&bytes),
+                    "chunk length {}",
+                    chunk_len
+                );
+            }
+            assert_eq!(marker.par_unmark(&bytes), marker.unmark(&bytes));
+        }
+    }
+
+    #[test]
+    fn test_overlapping_mark_and_tail() {
+        //The tail occurs inside the mark - it must not be picked up there
+        let marker = ByteMarker::new("xab", "ab");
+        let pieces = ["x", "a", "b", "xab", "zz"];
+        for _ in 0..50 {
+            let bytes = random_marked(&marker, &pieces, 40);
+            for chunk_len in [1, 2, 3, 4, 9] {
+                assert_eq!(
+                    marker.par_unmark_chunked(&bytes, chunk_len),
+                    marker.unmark(&bytes)
+                );
+            }
+        }
+        assert_eq!(marker.par_unmark(b""), None);
+    }
+}
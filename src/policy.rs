@@ -0,0 +1,33 @@
+//! ## Policies
+//!
+//! How readers and decoders react to oversized frames and to the tail.
+
+/// What a reader does after a frame exceeds the maximum frame length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum OverflowPolicy {
+    /// Stop reading - no further frames are returned.
+    #[default]
+    Abort,
+    /// Drop the offending bytes up to the next mark and carry on.
+    Discard,
+}
+
+/// What a reader does once it sees the tail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum TailMode {
+    /// The tail ends the stream.
+    #[default]
+    Stop,
+    /// The tail ends a batch - reading carries on with the next batch.
+    BatchEnd,
+}
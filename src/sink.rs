@@ -0,0 +1,232 @@
+//! ## MarkedSink
+//!
+//! Write side of `AsyncMarked` - a [futures_sink::Sink] that marks each frame it is
+//! handed and writes it to any [futures_io::AsyncWrite].
+
+use crate::{Byte, ByteMarker, MarkerConfig};
+use futures_io::AsyncWrite;
+use futures_sink::Sink;
+use std::future;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+/// Appends the mark to every frame sent, and the tail when the sink is closed - if
+/// any frame was sent.
+///
+/// A frame is only buffered until the next `poll_ready`/`poll_flush`, which write
+/// it out in full - the writer is never left holding half a frame. Use `close_with`
+/// to end the last frame with the tail instead of the mark.
+pub struct MarkedSink<W> {
+    writer: W,
+    marker: ByteMarker,
+    buffer: Vec<Byte>,
+    written: usize,
+    sent: bool,
+    tail_written: bool,
+}
+
+impl<W> MarkedSink<W>
+where
+    W: AsyncWrite + Unpin,
+{
+    pub fn with_defaults(w: W) -> Self {
+        Self::from_marker(w, ByteMarker::with_defaults())
+    }
+
+    pub fn new(w: W, mark: &str, tail: &str) -> Self {
+        Self::from_marker(w, ByteMarker::new(mark, tail))
+    }
+
+    /// Takes the mark and tail from `config` - the reading settings do not apply.
+    pub fn from_config(w: W, config: &MarkerConfig) -> Self {
+        Self::from_marker(w, ByteMarker::from_config(config))
+    }
+
+    pub fn from_marker(w: W, marker: ByteMarker) -> Self {
+        Self {
+            writer: w,
+            marker,
+            buffer: Vec::new(),
+            written: 0,
+            sent: false,
+            tail_written: false,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Sends `last` ended by the tail rather than the mark, then closes the sink -
+    /// `last|tail` instead of `last|mark|tail`. Without a tail, `last` takes the mark.
+    pub async fn close_with(&mut self, last: Vec<Byte>) -> io::Result<()> {
+        future::poll_fn(|cx| self.poll_write_buffer(cx)).await?;
+        self.buffer_frame(last, true);
+        future::poll_fn(|cx| self.poll_close_all(cx)).await
+    }
+
+    fn buffer_frame(&mut self, item: Vec<Byte>, last: bool) {
+        if self.buffer.is_empty() {
+            self.buffer = item;
+        } else {
+            self.buffer.extend(item);
+        }
+        if last && !self.marker.tail().is_empty() {
+            self.marker.mark_tail(&mut self.buffer);
+            self.tail_written = true;
+        } else {
+            self.marker.mark_bytes(&mut self.buffer);
+        }
+        self.sent = true;
+    }
+
+    fn poll_write_buffer(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.buffer.len() {
+            let n =
+                ready!(Pin::new(&mut self.writer).poll_write(cx, &self.buffer[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.buffer.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_flush_all(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_write_buffer(cx))?;
+        Pin::new(&mut self.writer).poll_flush(cx)
+    }
+
+    fn poll_close_all(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        //A tail on its own would read back as an empty frame
+        if self.sent && !self.tail_written {
+            self.marker.mark_tail(&mut self.buffer);
+            self.tail_written = true;
+        }
+        ready!(self.poll_flush_all(cx))?;
+        Pin::new(&mut self.writer).poll_close(cx)
+    }
+}
+
+impl<W> Sink<Vec<Byte>> for MarkedSink<W>
+where
+    W: AsyncWrite + Unpin,
+{
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_write_buffer(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Vec<Byte>) -> io::Result<()> {
+        self.get_mut().buffer_frame(item, false);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_flush_all(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_close_all(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::AsyncMarked;
+    use futures::executor::block_on;
+    use futures::io::Cursor;
+    use futures::{SinkExt, TryStreamExt};
+
+    //Takes a byte at a time, and only on every other poll
+    struct Dribble {
+        bytes: Vec<u8>,
+        ready: bool,
+    }
+
+    impl AsyncWrite for Dribble {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            this.ready = !this.ready;
+            if !this.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            this.bytes.push(buf[0]);
+            Poll::Ready(Ok(1))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn test_sink_writes_marks_and_tail() {
+        let mut sink = MarkedSink::new(
+            Dribble {
+                bytes: Vec::new(),
+                ready: false,
+            },
+            "sUfFiX",
+            "tAiL",
+        );
+        block_on(async {
+            sink.send(b"Streaming".to_vec()).await.unwrap();
+            sink.feed(b" with".to_vec()).await.unwrap();
+            sink.feed(b" marks".to_vec()).await.unwrap();
+            sink.close().await.unwrap();
+        });
+        assert_eq!(
+            sink.into_inner().bytes,
+            b"StreamingsUfFiX withsUfFiX markssUfFiXtAiL"
+        );
+
+        //Every frame is on the wire, mark included, once sent
+        let mut sink = MarkedSink::new(Cursor::new(Vec::new()), "sUfFiX", "tAiL");
+        block_on(sink.send(b"request".to_vec())).unwrap();
+        assert_eq!(sink.writer.get_ref(), b"requestsUfFiX");
+
+        //A sink closed without frames writes nothing - a lone tail reads as an empty frame
+        let mut sink = MarkedSink::new(Cursor::new(Vec::new()), "sUfFiX", "tAiL");
+        block_on(SinkExt::<Vec<u8>>::close(&mut sink)).unwrap();
+        assert!(sink.into_inner().into_inner().is_empty());
+
+        //Without a tail, the last frame keeps its mark
+        let mut sink = MarkedSink::new(Cursor::new(Vec::new()), "sUfFiX", "");
+        block_on(sink.close_with(b"last".to_vec())).unwrap();
+        assert_eq!(sink.into_inner().into_inner(), b"lastsUfFiX");
+    }
+
+    #[test]
+    fn test_sink_and_stream_round_trip() {
+        let frames = [b"Streaming".to_vec(), Vec::new(), b" and tail".to_vec()];
+        let mut sink = MarkedSink::new(Cursor::new(Vec::new()), "sUfFiX", "tAiL");
+        block_on(async {
+            for frame in frames[..2].iter().cloned() {
+                sink.send(frame).await.unwrap();
+            }
+            sink.close_with(frames[2].clone()).await.unwrap();
+        });
+
+        let mut marked = sink.into_inner();
+        marked.set_position(0);
+        let stream = AsyncMarked::new(marked, "sUfFiX", "tAiL");
+        let received: Vec<_> = block_on(stream.try_collect()).unwrap();
+        //The tail ends the last frame
+        assert_eq!(received, frames);
+    }
+}
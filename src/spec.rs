@@ -0,0 +1,189 @@
+//! ## Mark specs
+//!
+//! The text syntax marks and tails are written in outside of code - in the
+//! environment, config files and the built-in `byte_mark/byte_tail` files.
+//!
+//! - `hex:7e7e` - hex digits, two per byte.
+//! - `base64:fn4=` - standard base64, padding optional.
+//! - Anything else is taken as text, with the escapes `\n`, `\r`, `\t`, `\0`,
+//!   `\\` and `\xHH` for any byte.
+//!
+//! Line endings trailing a spec are dropped - a newline that is part of the mark
+//! has to be written as `\n`.
+
+use crate::{Byte, Error, Result};
+use alloc::format;
+#[cfg(feature = "serde")]
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Parses a mark or tail spec into its bytes.
+pub(crate) fn parse(spec: &str) -> Result<Vec<Byte>> {
+    let spec = spec.trim_end_matches(['\r', '\n']);
+    if let Some(digits) = spec.strip_prefix("hex:") {
+        return hex(digits);
+    }
+    if let Some(encoded) = spec.strip_prefix("base64:") {
+        return base64(encoded);
+    }
+    unescape(spec)
+}
+
+/// Writes `bytes` as a text spec that `parse` reads back as the same bytes -
+/// printable ASCII as is, anything else escaped.
+#[cfg(feature = "serde")]
+pub(crate) fn format(bytes: &[Byte]) -> String {
+    //Text starting like a prefixed spec must not be read as one
+    let prefixed = bytes.starts_with(b"hex:") || bytes.starts_with(b"base64:");
+    let mut spec = String::with_capacity(bytes.len());
+    for (i, byte) in bytes.iter().enumerate() {
+        match byte {
+            b'\n' => spec.push_str("\\n"),
+            b'\r' => spec.push_str("\\r"),
+            b'\t' => spec.push_str("\\t"),
+            b'\0' => spec.push_str("\\0"),
+            b'\\' => spec.push_str("\\\\"),
+            _ if i == 0 && prefixed => spec.push_str(&format!("\\x{:02x}", byte)),
+            b' '..=b'~' => spec.push(*byte as char),
+            _ => spec.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    spec
+}
+
+fn invalid<T>(spec: &str, reason: &str) -> Result<T> {
+    Err(Error::InvalidConfig(format!("Spec {:?}: {}", spec, reason)))
+}
+
+fn unescape(spec: &str) -> Result<Vec<Byte>> {
+    let mut bytes = Vec::with_capacity(spec.len());
+    let mut rest = spec.as_bytes();
+    while let Some((&byte, after)) = rest.split_first() {
+        rest = after;
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        let (&escaped, after) = match rest.split_first() {
+            Some(next) => next,
+            None => return invalid(spec, "dangling `\\`"),
+        };
+        rest = after;
+        bytes.push(match escaped {
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b't' => b'\t',
+            b'0' => b'\0',
+            b'\\' => b'\\',
+            b'x' if rest.len() >= 2 => {
+                let value = hex_pair(rest[0], rest[1]);
+                rest = &rest[2..];
+                match value {
+                    Some(value) => value,
+                    None => return invalid(spec, "`\\x` takes two hex digits"),
+                }
+            }
+            b'x' => return invalid(spec, "`\\x` takes two hex digits"),
+            _ => return invalid(spec, "unknown escape"),
+        });
+    }
+    Ok(bytes)
+}
+
+fn hex_pair(high: Byte, low: Byte) -> Option<Byte> {
+    let digit = |byte: Byte| (byte as char).to_digit(16).map(|digit| digit as Byte);
+    Some(digit(high)? << 4 | digit(low)?)
+}
+
+fn hex(digits: &str) -> Result<Vec<Byte>> {
+    let spec = || format!("hex:{}", digits);
+    if !digits.len().is_multiple_of(2) {
+        return invalid(&spec(), "odd number of hex digits");
+    }
+    digits
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| hex_pair(pair[0], pair[1]))
+        .collect::<Option<Vec<_>>>()
+        .map_or_else(|| invalid(&spec(), "not a hex digit"), Ok)
+}
+
+fn base64(encoded: &str) -> Result<Vec<Byte>> {
+    let spec = || format!("base64:{}", encoded);
+    let trimmed = encoded.trim_end_matches('=');
+    if encoded.len() - trimmed.len() > 2 || trimmed.len() % 4 == 1 {
+        return invalid(&spec(), "not valid base64");
+    }
+    let mut bytes = Vec::with_capacity(trimmed.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    for byte in trimmed.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return invalid(&spec(), "not a base64 character"),
+        };
+        bits = bits << 6 | value as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            bytes.push((bits >> bit_count) as Byte);
+            bits &= (1 << bit_count) - 1;
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_text_and_escapes() {
+        assert_eq!(parse("sUfFiX").unwrap(), b"sUfFiX");
+        assert_eq!(parse("sUfFiX\n").unwrap(), b"sUfFiX");
+        assert_eq!(parse("sUfFiX\r\n").unwrap(), b"sUfFiX");
+        assert_eq!(parse("sUfFiX\\n").unwrap(), b"sUfFiX\n");
+        assert_eq!(parse("\\r\\n").unwrap(), b"\r\n");
+        assert_eq!(parse("\\x00\\xfF\\t\\0\\\\").unwrap(), b"\x00\xff\t\0\\");
+        assert_eq!(parse("").unwrap(), b"");
+        for spec in ["\\", "\\q", "\\x", "\\x0", "\\xzz"] {
+            assert!(
+                matches!(parse(spec), Err(Error::InvalidConfig(_))),
+                "{}",
+                spec
+            );
+        }
+    }
+
+    #[test]
+    fn test_hex_and_base64() {
+        assert_eq!(parse("hex:7e7E00ff").unwrap(), b"\x7e\x7e\x00\xff");
+        assert_eq!(parse("hex:").unwrap(), b"");
+        assert!(parse("hex:7e7").is_err());
+        assert!(parse("hex:7g").is_err());
+
+        assert_eq!(parse("base64:c1VmRmlY").unwrap(), b"sUfFiX");
+        assert_eq!(parse("base64:fn4=").unwrap(), b"~~");
+        assert_eq!(parse("base64:fn4").unwrap(), b"~~");
+        assert_eq!(parse("base64:AP8=\n").unwrap(), b"\x00\xff");
+        for spec in ["base64:f", "base64:fn4===", "base64:fn-4"] {
+            assert!(parse(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_format_round_trips() {
+        assert_eq!(format(b"sUfFiX\n"), "sUfFiX\\n");
+        assert_eq!(format(b"\r\n\x00\xff\\"), "\\r\\n\\0\\xff\\\\");
+        assert_eq!(format(b"hex:7e"), "\\x68ex:7e");
+        let all: Vec<Byte> = (0..=255).collect();
+        for bytes in [&all[..], b"hex:7e", b"base64:", b"hexa", b" spaced "] {
+            assert_eq!(parse(&format(bytes)).unwrap(), bytes);
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! ## Split
+//!
+
+use crate::{Boundary, Byte, ByteMarker};
+use core::iter::FusedIterator;
+
+/// A run of bytes handed out by `ByteMarker::split`, tagged with what ended it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Segment<'b> {
+    /// Bytes followed by the mark.
+    Frame(&'b [Byte]),
+    /// Bytes followed by the tail - always the last segment.
+    Tail(&'b [Byte]),
+    /// Trailing bytes followed by neither the mark nor the tail - always the last segment.
+    LeftOver(&'b [Byte]),
+}
+
+impl<'b> Segment<'b> {
+    /// The segment bytes, whatever ended them.
+    pub fn bytes(&self) -> &'b [Byte] {
+        match self {
+            Segment::Frame(bytes) | Segment::Tail(bytes) | Segment::LeftOver(bytes) => bytes,
+        }
+    }
+}
+
+/// Lazy iterator over the segments of a marked byte slice. Created by `ByteMarker::split`.
+pub struct Split<'s, 'b> {
+    marker: &'s ByteMarker,
+    bytes: &'b [Byte],
+    pos: usize,
+    done: bool,
+}
+
+impl<'s, 'b> Split<'s, 'b> {
+    pub(crate) fn new(marker: &'s ByteMarker, bytes: &'b [Byte]) -> Self {
+        Self {
+            marker,
+            bytes,
+            pos: 0,
+            done: false,
+        }
+    }
+}
+
+impl<'b> Iterator for Split<'_, 'b> {
+    type Item = Segment<'b>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let start = self.pos;
+        match self.marker.find_boundary(self.bytes, start) {
+            Some((index, Boundary::Mark)) => {
+                self.pos = index + self.marker.boundary_len(Boundary::Mark);
+                Some(Segment::Frame(&self.bytes[start..index]))
+            }
+            Some((index, Boundary::Tail)) => {
+                self.done = true;
+                Some(Segment::Tail(&self.bytes[start..index]))
+            }
+            None => {
+                self.done = true;
+                Some(&self.bytes[start..])
+                    .filter(|left_over| !left_over.is_empty())
+                    .map(Segment::LeftOver)
+            }
+        }
+    }
+}
+
+impl FusedIterator for Split<'_, '_> {}
@@ -0,0 +1,269 @@
+//! ## AsyncMarked
+//!
+//! Async counterpart of `Marked` - a [futures_core::Stream] of frames read off any
+//! [futures_io::AsyncBufRead], so it works the same under tokio, smol or async-std.
+
+use crate::{
+    Byte, ByteMarker, Checksum, Decoder, DecoderState, MarkerConfig, OverflowPolicy, TailMode,
+};
+use futures_core::Stream;
+use futures_io::AsyncBufRead;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+/// Streams the frames demarcated by marks in an async reader.
+///
+/// Frames are yielded as soon as their mark is read. All state is kept in the
+/// stream itself, so dropping a pending `next()` loses no bytes - the next poll
+/// carries on where the previous one left off.
+pub struct AsyncMarked<R> {
+    reader: R,
+    decoder: Decoder,
+    failed: bool,
+}
+
+impl<R> AsyncMarked<R>
+where
+    R: AsyncBufRead + Unpin,
+{
+    pub fn with_defaults(r: R) -> Self {
+        Self::from_marker(r, ByteMarker::with_defaults())
+    }
+
+    pub fn new(r: R, mark: &str, tail: &str) -> Self {
+        Self::from_marker(r, ByteMarker::new(mark, tail))
+    }
+
+    /// Takes the mark, tail, limits, overflow policy, tail mode, strictness and
+    /// checksum from `config`.
+    pub fn from_config(r: R, config: &MarkerConfig) -> Self {
+        Self {
+            reader: r,
+            decoder: Decoder::from_config(config),
+            failed: false,
+        }
+    }
+
+    pub fn from_marker(r: R, marker: ByteMarker) -> Self {
+        Self {
+            reader: r,
+            decoder: Decoder::from_marker(marker),
+            failed: false,
+        }
+    }
+
+    /// Frames longer than `len` bytes are reported as `Error::FrameTooLarge`
+    /// instead of being buffered.
+    pub fn max_frame_len(mut self, len: usize) -> Self {
+        self.decoder = self.decoder.max_frame_len(len);
+        self
+    }
+
+    /// Reading stops with `Error::StreamTooLarge` once the frames and marks read
+    /// come to more than `len` bytes.
+    pub fn max_stream_len(mut self, len: usize) -> Self {
+        self.decoder = self.decoder.max_stream_len(len);
+        self
+    }
+
+    /// Sets what happens after a frame exceeds the maximum frame length.
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.decoder = self.decoder.overflow_policy(policy);
+        self
+    }
+
+    /// Sets whether the tail ends the stream or just the current batch.
+    pub fn tail_mode(mut self, mode: TailMode) -> Self {
+        self.decoder = self.decoder.tail_mode(mode);
+        self
+    }
+
+    /// In strict mode, bytes left without a mark or tail when the reader ends are
+    /// reported as `Error::Truncated` rather than being returned as a frame.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.decoder = self.decoder.strict(strict);
+        self
+    }
+
+    /// Expects every frame to carry a `checksum` - see `Decoder::checksum`.
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.decoder = self.decoder.checksum(checksum);
+        self
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R> Stream for AsyncMarked<R>
+where
+    R: AsyncBufRead + Unpin,
+{
+    type Item = io::Result<Vec<Byte>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(frame) = this.decoder.next_frame() {
+                return Poll::Ready(Some(frame.map(|frame| frame.data).map_err(Into::into)));
+            }
+            if this.failed || this.decoder.state() == DecoderState::Done {
+                return Poll::Ready(None);
+            }
+            let buf = match ready!(Pin::new(&mut this.reader).poll_fill_buf(cx)) {
+                Ok(buf) => buf,
+                Err(err) => {
+                    this.failed = true;
+                    return Poll::Ready(Some(Err(err)));
+                }
+            };
+            if buf.is_empty() {
+                this.decoder.finish();
+                continue;
+            }
+            //Handed over in full - the decoder keeps what is not yet a frame
+            let fetched = buf.len();
+            this.decoder.push(buf);
+            Pin::new(&mut this.reader).consume(fetched);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Error;
+    use futures::executor::block_on;
+    use futures::io::{BufReader, Cursor};
+    use futures::task::noop_waker_ref;
+    use futures::{FutureExt, StreamExt, TryStreamExt};
+
+    //Hands out one byte at a time, and only on every other poll
+    struct Trickle<'b> {
+        bytes: &'b [u8],
+        ready: bool,
+    }
+
+    impl AsyncBufRead for Trickle<'_> {
+        fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+            let this = self.get_mut();
+            this.ready = !this.ready;
+            if !this.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            Poll::Ready(Ok(&this.bytes[..this.bytes.len().min(1)]))
+        }
+
+        fn consume(self: Pin<&mut Self>, amt: usize) {
+            let this = self.get_mut();
+            this.bytes = &this.bytes[amt..];
+        }
+    }
+
+    impl futures::io::AsyncRead for Trickle<'_> {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            let bytes = ready!(Pin::new(&mut *this).poll_fill_buf(cx))?;
+            let n = bytes.len().min(buf.len());
+            buf[..n].copy_from_slice(&bytes[..n]);
+            Pin::new(this).consume(n);
+            Poll::Ready(Ok(n))
+        }
+    }
+
+    #[test]
+    fn test_stream_with_marks_and_tail() {
+        let message = "StreamingsUfFiX withsUfFiX markssUfFiX and tailtAiLignored";
+        for capacity in [1, 3, 7, message.len()] {
+            let reader = BufReader::with_capacity(capacity, Cursor::new(message.as_bytes()));
+            let stream = AsyncMarked::new(reader, "sUfFiX", "tAiL");
+            let frames: Vec<_> = block_on(stream.try_collect()).unwrap();
+            assert_eq!(
+                frames,
+                [&b"Streaming"[..], b" with", b" marks", b" and tail"],
+                "capacity {}",
+                capacity
+            );
+        }
+    }
+
+    #[test]
+    fn test_dropping_pending_next_loses_no_bytes() {
+        let mut stream = AsyncMarked::new(
+            Trickle {
+                bytes: b"onesUfFiXtwosUfFiXthree",
+                ready: false,
+            },
+            "sUfFiX",
+            "tAiL",
+        );
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut frames = Vec::new();
+        loop {
+            //Every future is dropped straight after a single poll
+            match stream.next().poll_unpin(&mut cx) {
+                Poll::Ready(Some(frame)) => frames.push(frame.unwrap()),
+                Poll::Ready(None) => break,
+                Poll::Pending => continue,
+            }
+        }
+        assert_eq!(frames, [&b"one"[..], b"two", b"three"]);
+    }
+
+    #[test]
+    fn test_strict_and_batches() {
+        let reader = Cursor::new(&b"onetAiLtwosUfFiXthr"[..]);
+        let mut stream = AsyncMarked::new(reader, "sUfFiX", "tAiL")
+            .tail_mode(TailMode::BatchEnd)
+            .strict(true);
+        block_on(async {
+            assert_eq!(stream.next().await.unwrap().unwrap(), b"one");
+            assert_eq!(stream.next().await.unwrap().unwrap(), b"two");
+            let err = stream.next().await.unwrap().unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(stream.next().await.is_none());
+        });
+    }
+
+    #[test]
+    fn test_limits() {
+        fn reason(err: io::Error) -> Error {
+            *err.into_inner().unwrap().downcast::<Error>().unwrap()
+        }
+
+        let reader = Cursor::new(&b"onesUfFiXway too longsUfFiXtwosUfFiXthreesUfFiX"[..]);
+        let mut stream = AsyncMarked::new(reader, "sUfFiX", "tAiL")
+            .max_frame_len(4)
+            .overflow_policy(OverflowPolicy::Discard)
+            .max_stream_len(36);
+        block_on(async {
+            assert_eq!(stream.next().await.unwrap().unwrap(), b"one");
+            let err = stream.next().await.unwrap().unwrap_err();
+            assert!(matches!(reason(err), Error::FrameTooLarge { limit: 4 }));
+            assert_eq!(stream.next().await.unwrap().unwrap(), b"two");
+            let err = stream.next().await.unwrap().unwrap_err();
+            assert!(matches!(reason(err), Error::StreamTooLarge { limit: 36 }));
+            assert!(stream.next().await.is_none());
+        });
+
+        //A frame that never ends is cut off without being buffered in full
+        let endless = vec![b'x'; 1 << 16];
+        let reader = BufReader::with_capacity(64, Cursor::new(&endless[..]));
+        let config = MarkerConfig::new("sUfFiX", "tAiL")
+            .unwrap()
+            .max_frame_len(100);
+        let mut stream = AsyncMarked::from_config(reader, &config);
+        block_on(async {
+            let err = stream.next().await.unwrap().unwrap_err();
+            assert!(matches!(reason(err), Error::FrameTooLarge { limit: 100 }));
+            assert!(stream.next().await.is_none());
+        });
+    }
+}
@@ -2,10 +2,13 @@
 mod tests {
     use byte_marks::ByteMarker;
     use byte_marks::Marked;
+    use byte_marks::{marker, ConstMarker};
+    use byte_marks::{Checksum, Error, Frame, NotMarked, Segment, Terminator};
     use rand::Rng;
     use std::io::Cursor;
 
     #[test]
+    #[allow(clippy::vec_init_then_push)]
     fn test_streaming_with_marks_and_tail() {
         //message_stream -> stream of messages -> msg|suffix|msg|suffix|msg|tail
         let message_stream = "StreamingsUfFiX withsUfFiX markssUfFiX and tailtAiL";
@@ -89,4 +92,387 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn unmark_batches_test() {
+        let marker = ByteMarker::new("sUfFiX", "tAiL");
+        let message = "onesUfFiXtwotAiLthreesUfFiXfourtAiLfivesUfFiXsix";
+
+        let (batches, left_over) = marker.unmark_batches(message.as_bytes()).unwrap();
+        assert_eq!(
+            batches,
+            vec![
+                vec!["one".as_bytes(), "two".as_bytes()],
+                vec!["three".as_bytes(), "four".as_bytes()],
+                vec!["five".as_bytes()],
+            ]
+        );
+        assert_eq!(left_over, Some("six".as_bytes()));
+
+        //Plain unmark stops at the first tail
+        let (unmarked, left_over) = marker.unmark(message.as_bytes()).unwrap();
+        assert_eq!(unmarked, vec!["one".as_bytes(), "two".as_bytes()]);
+        assert_eq!(left_over, None);
+    }
+
+    #[test]
+    fn unmark_frames_test() {
+        let marker = ByteMarker::new("sUfFiX", "tAiL");
+        let frames = marker.unmark_frames("onesUfFiXtwosUfFiXthree".as_bytes());
+        assert_eq!(
+            frames,
+            vec![
+                Frame {
+                    data: "one".as_bytes(),
+                    start_offset: 0,
+                    terminator: Terminator::Mark
+                },
+                Frame {
+                    data: "two".as_bytes(),
+                    start_offset: 9,
+                    terminator: Terminator::Mark
+                },
+                Frame {
+                    data: "three".as_bytes(),
+                    start_offset: 18,
+                    terminator: Terminator::Eof
+                },
+            ]
+        );
+        assert_eq!(frames[2].end_offset(), 23);
+
+        let frames = marker.unmark_frames("onetAiLtwosUfFiX".as_bytes());
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].terminator, Terminator::Tail);
+    }
+
+    #[test]
+    fn try_unmark_test() {
+        let marker = ByteMarker::new("sUfFiX", "tAiL");
+        assert_eq!(
+            marker.try_unmark("onesUfFiXtwotAiL".as_bytes()).unwrap(),
+            vec!["one".as_bytes(), "two".as_bytes()]
+        );
+        assert!(marker.try_unmark("".as_bytes()).unwrap().is_empty());
+        assert!(matches!(
+            marker.try_unmark("onesUfFiXtwosUfF".as_bytes()),
+            Err(Error::Truncated { bytes: 7 })
+        ));
+    }
+
+    #[test]
+    fn split_test() {
+        let marker = ByteMarker::new("sUfFiX", "tAiL");
+        let segments: Vec<_> = marker.split("onesUfFiXsUfFiXtwo".as_bytes()).collect();
+        assert_eq!(
+            segments,
+            vec![
+                Segment::Frame("one".as_bytes()),
+                Segment::Frame("".as_bytes()),
+                Segment::LeftOver("two".as_bytes()),
+            ]
+        );
+
+        let mut split = marker.split("onetAiLtwosUfFiX".as_bytes());
+        assert_eq!(split.next(), Some(Segment::Tail("one".as_bytes())));
+        assert_eq!(split.next(), None);
+
+        assert_eq!(marker.split("".as_bytes()).count(), 0);
+        assert_eq!(marker.split("onesUfFiX".as_bytes()).count(), 1);
+    }
+
+    #[test]
+    fn split_agrees_with_unmark() {
+        let marker = ByteMarker::new("sUfFiX", "tAiL");
+        let message = "StreamingsUfFiX withsUfFiX markssUfFiX and tail";
+        let (unmarked, left_over) = marker.unmark(message.as_bytes()).unwrap();
+        let segments: Vec<_> = marker.split(message.as_bytes()).collect();
+        assert_eq!(segments.len(), unmarked.len() + 1);
+        for (segment, frame) in segments.iter().zip(unmarked) {
+            assert_eq!(segment, &Segment::Frame(frame));
+        }
+        assert_eq!(segments.last().unwrap().bytes(), left_over.unwrap());
+    }
+
+    #[test]
+    fn unmark_in_place_test() {
+        let marker = ByteMarker::new("sUfFiX", "tAiL");
+
+        let mut bytes = "onesUfFiXsUfFiXtwosUfFiXthr".as_bytes().to_vec();
+        let capacity = bytes.capacity();
+        let ranges = marker.unmark_in_place(&mut bytes);
+        assert_eq!(ranges, vec![0..3, 3..3, 3..6]);
+        assert_eq!(bytes, "onetwothr".as_bytes());
+        assert_eq!(bytes.capacity(), capacity);
+
+        let mut bytes = "onesUfFiXtwotAiLignored".as_bytes().to_vec();
+        let ranges = marker.unmark_in_place(&mut bytes);
+        assert_eq!(ranges, vec![0..3, 3..6]);
+        assert_eq!(bytes, "onetwo".as_bytes());
+
+        let mut bytes = Vec::new();
+        assert!(marker.unmark_in_place(&mut bytes).is_empty());
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn strip_test() {
+        let marker = ByteMarker::new("sUfFiX", "tAiL");
+
+        let mut bytes = "payloadsUfFiX".as_bytes().to_vec();
+        assert_eq!(marker.strip_tail(&mut bytes), Err(NotMarked));
+        assert_eq!(marker.strip_mark(&mut bytes), Ok(()));
+        assert_eq!(bytes, "payload".as_bytes());
+        assert_eq!(marker.strip_mark(&mut bytes), Err(NotMarked));
+        assert_eq!(bytes, "payload".as_bytes());
+
+        let mut short = "iX".as_bytes().to_vec();
+        assert_eq!(marker.strip_mark(&mut short), Err(NotMarked));
+        assert_eq!(short, "iX".as_bytes());
+
+        let mut bytes = "payloadsUfFiXtAiL".as_bytes().to_vec();
+        assert_eq!(marker.strip_all(&mut bytes), Ok(()));
+        assert_eq!(bytes, "payload".as_bytes());
+        assert_eq!(marker.strip_all(&mut bytes), Err(NotMarked));
+
+        let no_tail = ByteMarker::new("sUfFiX", "");
+        let mut bytes = "payload".as_bytes().to_vec();
+        assert_eq!(no_tail.strip_tail(&mut bytes), Err(NotMarked));
+        assert_eq!(bytes, "payload".as_bytes());
+    }
+
+    #[test]
+    fn mark_all_test() {
+        let marker = ByteMarker::new("sUfFiX", "tAiL");
+        let payloads = ["Streaming", " with", "", " marks"];
+
+        let marked = marker.mark_all(payloads, false);
+        assert_eq!(
+            marked,
+            "StreamingsUfFiX withsUfFiXsUfFiX markssUfFiX".as_bytes()
+        );
+        assert_eq!(marked.capacity(), marked.len());
+
+        let marked = marker.mark_all(payloads.iter().map(|s| s.as_bytes().to_vec()), true);
+        assert_eq!(
+            marked,
+            "StreamingsUfFiX withsUfFiXsUfFiX markstAiL".as_bytes()
+        );
+        assert_eq!(marked.capacity(), marked.len());
+        let (unmarked, left_over) = marker.unmark(&marked).unwrap();
+        assert_eq!(unmarked, payloads.map(str::as_bytes));
+        assert_eq!(left_over, None);
+
+        assert_eq!(marker.mark_all(Vec::<&[u8]>::new(), false), b"");
+        //A lone tail would read back as an empty frame
+        assert_eq!(marker.mark_all(Vec::<&[u8]>::new(), true), b"");
+        //Without a tail, the last payload keeps its mark
+        let no_tail = ByteMarker::new("sUfFiX", "");
+        let marked = no_tail.mark_all(payloads, true);
+        assert_eq!(marked, no_tail.mark_all(payloads, false));
+        assert_eq!(marked.capacity(), marked.len());
+        assert_eq!(no_tail.mark_all(Vec::<&[u8]>::new(), true), b"");
+
+        let mut marked = Vec::new();
+        for payload in payloads {
+            marker.mark_into(&mut marked, payload.as_bytes());
+        }
+        assert_eq!(marked, marker.mark_all(payloads, false));
+    }
+
+    #[test]
+    fn shared_marker_test() {
+        fn assert_shareable<T: Clone + Send + Sync + std::fmt::Debug + 'static>() {}
+        assert_shareable::<ByteMarker>();
+
+        let marker = ByteMarker::new("sUfFiX", "tAiL");
+        assert_eq!(
+            format!("{:?}", marker),
+            r#"ByteMarker { mark: "sUfFiX", tail: "tAiL" }"#
+        );
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let marker = marker.clone();
+                std::thread::spawn(move || {
+                    let message = format!("onesUfFiXthread {}tAiL", i);
+                    let mut cursor = Cursor::new(message.into_bytes());
+                    Marked::from_marker(&mut cursor, marker).collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        for (i, handle) in handles.into_iter().enumerate() {
+            let frames = handle.join().unwrap();
+            assert_eq!(
+                frames,
+                [b"one".to_vec(), format!("thread {}", i).into_bytes()]
+            );
+        }
+    }
+
+    #[test]
+    fn const_marker_test() {
+        const MARKER: ConstMarker = marker!(b"sUfFiX", b"tAiL");
+        static NO_TAIL: ConstMarker = marker!(b"\r\n");
+        let runtime = ByteMarker::new("sUfFiX", "tAiL");
+        let marker = ByteMarker::from_const(&MARKER);
+        assert_eq!(format!("{:?}", marker), format!("{:?}", runtime));
+
+        let random_texts = ["Some random", "sUf", "FiX", "tA", "iL", "strings", ""];
+        let mut randomizer = rand::thread_rng();
+        for _ in 0..100 {
+            let mut bytes = Vec::new();
+            for _ in 0..randomizer.gen_range(0..50) {
+                let text = random_texts[randomizer.gen_range(0..random_texts.len())];
+                bytes.extend(text.as_bytes());
+                if randomizer.gen_bool(0.3) {
+                    runtime.mark_bytes(&mut bytes);
+                }
+            }
+            assert_eq!(marker.unmark(&bytes), runtime.unmark(&bytes));
+        }
+
+        let marker = ByteMarker::from_const(&NO_TAIL);
+        let (unmarked, left_over) = marker.unmark(b"one\r\ntwo\r\nthree").unwrap();
+        assert_eq!(unmarked, [&b"one"[..], b"two"]);
+        assert_eq!(left_over, Some(&b"three"[..]));
+    }
+
+    #[test]
+    fn checksum_test() {
+        let marker = ByteMarker::new("\r\n", "");
+        let payloads = ["first", "", "third frame", "fourth"];
+        for checksum in [Checksum::Crc16, Checksum::Crc32, Checksum::Crc32c] {
+            let mut bytes = Vec::new();
+            for payload in payloads {
+                marker.mark_checked(&mut bytes, payload.as_bytes(), checksum);
+            }
+            bytes.extend(b"left");
+            let (unmarked, left_over) = marker.unmark_checked(&bytes, checksum).unwrap();
+            assert_eq!(unmarked, payloads.map(str::as_bytes));
+            assert_eq!(left_over, Some(&b"left"[..]));
+
+            //Any single flipped bit in a payload is caught
+            let mut randomizer = rand::thread_rng();
+            let frame_len = payloads[2].len() + checksum.encoded_len() + 2;
+            let offset =
+                payloads[0].len() + payloads[1].len() + 2 * (frame_len - payloads[2].len());
+            let mut corrupted = bytes.clone();
+            corrupted[offset + randomizer.gen_range(0..payloads[2].len())] ^=
+                1 << randomizer.gen_range(0..8);
+            assert!(matches!(
+                marker.unmark_checked(&corrupted, checksum),
+                Err(Error::ChecksumMismatch { frame_index: 2, offset: o }) if o == offset
+            ));
+            //Nor are frames without a checksum taken for checked ones
+            assert!(marker.unmark_checked(b"first\r\n", checksum).is_err());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "hmac")]
+    fn signed_frames_test() {
+        use byte_marks::{AuthFailure, Signer, Verifier, AUTH_LEN};
+
+        let marker = ByteMarker::new("\r\n", "");
+        let mut signer = Signer::new(1, b"first key");
+        let mut verifier = Verifier::new().with_key(1, b"first key");
+        let mut bytes = Vec::new();
+        marker
+            .mark_signed(&mut bytes, b"before", &mut signer)
+            .unwrap();
+        //Rotated mid session - the reader takes on the new key ahead of the writer
+        verifier.add_key(2, b"second key");
+        signer.rotate(2, b"second key");
+        marker
+            .mark_signed(&mut bytes, b"after", &mut signer)
+            .unwrap();
+        let (unmarked, left_over) = marker.unmark_verified(&bytes, &mut verifier).unwrap();
+        assert_eq!(unmarked, [&b"before"[..], b"after"]);
+        assert_eq!(left_over, None);
+
+        //The same frames again are replays
+        verifier.remove_key(1);
+        assert!(matches!(
+            marker.unmark_verified(&bytes, &mut verifier),
+            Err(Error::Unauthenticated {
+                frame_index: 0,
+                offset: 0,
+                reason: AuthFailure::UnknownKey(1)
+            })
+        ));
+        let second = &bytes[b"before".len() + AUTH_LEN + 2..];
+        assert!(matches!(
+            marker.unmark_verified(second, &mut verifier),
+            Err(Error::Unauthenticated {
+                reason: AuthFailure::BadTag,
+                ..
+            })
+        ));
+
+        //The tag could run into a tail that starts with a hex digit
+        let marker = ByteMarker::new("\r\n", "0x");
+        let mut bytes = Vec::new();
+        assert!(matches!(
+            marker.mark_signed(&mut bytes, b"before", &mut signer),
+            Err(Error::InvalidConfig(_))
+        ));
+        assert!(bytes.is_empty());
+        assert!(matches!(
+            marker.unmark_verified(b"before\r\n", &mut verifier),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "aead")]
+    fn encrypted_frames_test() {
+        use byte_marks::{Cipher, Decryptor, Encryptor};
+
+        let key = *b"an example very very secret key.";
+        let marker = ByteMarker::with_defaults();
+        let payloads = [&b"first"[..], b"", b"sUfFiX\n inside", b"\x00\xff"];
+        for cipher in [Cipher::ChaCha20Poly1305, Cipher::Aes256Gcm] {
+            let mut encryptor = Encryptor::new(cipher, &key, 42);
+            let mut bytes = Vec::new();
+            for payload in payloads {
+                marker
+                    .mark_encrypted(&mut bytes, payload, &mut encryptor)
+                    .unwrap();
+            }
+            //The mark inside the payload is hidden by the encryption
+            let mut decryptor = Decryptor::new(cipher, &key, 42);
+            let (unmarked, left_over) = marker.unmark_decrypted(&bytes, &mut decryptor).unwrap();
+            assert_eq!(unmarked, payloads);
+            assert_eq!(left_over, None);
+
+            let mut reader = Cursor::new(&bytes);
+            let decryptor = Decryptor::new(cipher, &key, 42);
+            let marked = Marked::with_defaults(&mut reader).decryptor(decryptor);
+            assert_eq!(marked.collect::<Vec<_>>(), payloads);
+
+            let mut wrong_stream = Decryptor::new(cipher, &key, 43);
+            assert!(matches!(
+                marker.unmark_decrypted(&bytes, &mut wrong_stream),
+                Err(Error::Undecryptable {
+                    frame_index: 0,
+                    offset: 0
+                })
+            ));
+        }
+
+        //Hex digits could run into a mark that starts or ends with one
+        let marker = ByteMarker::new("cafe", "");
+        let mut encryptor = Encryptor::new(Cipher::Aes256Gcm, &key, 42);
+        let mut bytes = Vec::new();
+        assert!(matches!(
+            marker.mark_encrypted(&mut bytes, b"first", &mut encryptor),
+            Err(Error::InvalidConfig(_))
+        ));
+        assert!(bytes.is_empty());
+        let mut decryptor = Decryptor::new(Cipher::Aes256Gcm, &key, 42);
+        assert!(matches!(
+            marker.unmark_decrypted(b"00cafe", &mut decryptor),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
 }